@@ -1,4 +1,5 @@
 use anyhow::Context;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs::File;
 use std::path::Path;
 use std::path::PathBuf;
@@ -49,29 +50,631 @@ pub enum Commands {
         /// Keep a copy of json docs and raw images
         #[clap(long)]
         store: bool,
-        /// Publish all pages (ignore publication status)
+        /// Publish all pages (ignore publication status and dates)
         #[clap(long)]
         all: bool,
+        /// Publish unpublished pages as drafts, to preview them with `hugo server -D`
+        #[clap(long)]
+        drafts: bool,
+        /// Publish the site as it will be on this date, for scheduled and expiring pages
+        #[clap(long)]
+        as_of: Option<String>,
     },
+
+    /// Check the table of contents for problems, without publishing anything
+    CheckToc,
 }
 
 //----- Config file
 
 #[derive(Deserialize, Debug)]
 pub struct Config {
-    pub toc_spreadsheet_url: String,
+    #[serde(default)]
+    pub toc_source: TocSource,
+    pub toc_spreadsheet_url: Option<String>,
+    /// Name of the ToC spreadsheet tab. Defaults to the first one.
+    pub toc_sheet: Option<String>,
+    #[serde(default)]
+    pub toc_reader: TocReader,
+    /// Root folder of the site when the ToC source is `drive_folder`
+    pub toc_folder_url: Option<String>,
     #[serde(default = "default_download_dir")]
     pub download_dir: PathBuf,
     pub hugo_site_dir: PathBuf,
     pub credentials: Option<PathBuf>,
     pub concurrency: Option<usize>,
     pub default_author: Option<String>,
+    /// IANA timezone of the site, used to interpret ToC dates that have no offset.
+    #[serde(default = "default_timezone", deserialize_with = "deser_timezone")]
+    pub timezone: chrono_tz::Tz,
+    /// `strftime`-like formats of ToC dates, tried in order. ISO 8601 dates and date-times are
+    /// always accepted. Formats without a time are dates at midnight.
+    #[serde(default = "default_date_formats")]
+    pub date_formats: Vec<String>,
+    /// Custom taxonomies, as ToC column -> Hugo front matter key. Columns contain comma or
+    /// semicolon separated lists of terms.
+    #[serde(default)]
+    pub taxonomies: BTreeMap<String, String>,
+    /// Types of the extra ToC columns that are forwarded to the front matter. Columns are
+    /// strings by default. Dotted column names (`params.hero.color`) are nested keys.
+    #[serde(default)]
+    pub column_types: BTreeMap<String, ColumnType>,
+    #[serde(default)]
+    pub languages: LanguagesConfig,
+    /// Rules giving the content directory and bundle type of pages. By default, pages with a
+    /// category are posts in `content/posts`.
+    #[serde(default = "default_layout")]
+    pub layout: Vec<LayoutRule>,
+    #[serde(default)]
+    pub menus: MenusConfig,
+    #[serde(default)]
+    pub navigation: NavigationConfig,
+    #[serde(default)]
+    pub front_matter: FrontMatterConfig,
+    #[serde(default)]
+    pub summary: SummaryConfig,
+    #[serde(default)]
+    pub data: DataConfig,
+    #[serde(default)]
+    pub styling: StylingConfig,
+    #[serde(default)]
+    pub sanitize: SanitizeConfig,
+    #[serde(default)]
+    pub links: LinksConfig,
+    #[serde(default)]
+    pub drive_links: DriveLinksConfig,
+    #[serde(default)]
+    pub drive_metadata: DriveMetadataConfig,
+    #[serde(default)]
+    pub suggestions: SuggestionsConfig,
 }
 
 fn default_download_dir() -> PathBuf {
     "gdoc_data".into()
 }
 
+fn default_timezone() -> chrono_tz::Tz {
+    chrono_tz::UTC
+}
+
+fn deser_timezone<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<chrono_tz::Tz, D::Error> {
+    let s = <String as serde::Deserialize>::deserialize(deserializer)?;
+    s.parse().map_err(serde::de::Error::custom)
+}
+
+fn default_date_formats() -> Vec<String> {
+    // Format of GSheets CSV exports with a European locale
+    vec!["%d/%m/%Y %H:%M:%S".to_string()]
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TocSource {
+    /// The ToC spreadsheet, with one row per page
+    #[default]
+    Sheet,
+    /// The hierarchy of GDocs in a Drive folder
+    DriveFolder,
+}
+
+/// A layout rule. All the conditions that are present must match.
+#[derive(Deserialize, Debug, Default)]
+pub struct LayoutRule {
+    /// Pages in this category (case-insensitive), or in any category with `*`
+    pub category: Option<String>,
+    /// Pages with this value in the `section` ToC column
+    pub section: Option<String>,
+    /// Pages whose slug is or starts with this path
+    pub slug_prefix: Option<String>,
+    /// Directory, relative to the content directory, where pages are written at their slug. If
+    /// the rule has a slug prefix, it's replaced by this directory.
+    pub dir: Option<String>,
+    #[serde(default)]
+    pub bundle: Bundle,
+}
+
+fn default_layout() -> Vec<LayoutRule> {
+    vec![LayoutRule {
+        category: Some("*".to_string()),
+        dir: Some("posts".to_string()),
+        bundle: Bundle::File,
+        ..LayoutRule::default()
+    }]
+}
+
+/// How a page is written. See https://gohugo.io/content-management/page-bundles/
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Bundle {
+    /// A branch bundle if the page has child pages, a leaf bundle otherwise
+    #[default]
+    Auto,
+    /// `<path>/index.html`
+    Leaf,
+    /// `<path>/_index.html`
+    Branch,
+    /// `<path>.html`
+    File,
+}
+
+/// Hugo menus built from the `menu` and `menu_parent` ToC columns.
+#[derive(Deserialize, Debug)]
+pub struct MenusConfig {
+    #[serde(default)]
+    pub output: MenuOutput,
+    /// Path of the menu data file, relative to the Hugo site
+    #[serde(default = "default_menu_data_file")]
+    pub data_file: PathBuf,
+}
+
+impl Default for MenusConfig {
+    fn default() -> Self {
+        MenusConfig {
+            output: MenuOutput::default(),
+            data_file: default_menu_data_file(),
+        }
+    }
+}
+
+fn default_menu_data_file() -> PathBuf {
+    "data/menu.yml".into()
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MenuOutput {
+    /// `menus` entries in the pages front matter
+    #[default]
+    FrontMatter,
+    /// A data file with all menus, for use in templates with `site.Data`
+    DataFile,
+}
+
+/// Navigation front matter computed from the page hierarchy given by slugs. Links only point to
+/// published pages.
+#[derive(Deserialize, Debug, Default)]
+pub struct NavigationConfig {
+    /// `breadcrumbs`: title and url of the ancestors of a page, from the root
+    #[serde(default)]
+    pub breadcrumbs: bool,
+    /// `prev` and `next`: the siblings of a page, ordered by weight
+    #[serde(default)]
+    pub prev_next: bool,
+    /// `children`: the child pages of a page, ordered by weight
+    #[serde(default)]
+    pub children: bool,
+}
+
+/// Format and keys of the pages front matter.
+#[derive(Deserialize, Debug, Default)]
+pub struct FrontMatterConfig {
+    #[serde(default)]
+    pub format: FrontMatterFormat,
+    /// Renamed front matter keys, as built-in key -> new key. Keys mapped to `null` or an empty
+    /// string are dropped.
+    #[serde(default)]
+    pub keys: BTreeMap<String, Option<String>>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FrontMatterFormat {
+    /// Between `---` lines
+    #[default]
+    Yaml,
+    /// Between `+++` lines
+    Toml,
+    /// A JSON object
+    Json,
+}
+
+/// How the `summary` and `description` front matter of GDocs are extracted.
+#[derive(Deserialize, Debug)]
+pub struct SummaryConfig {
+    #[serde(default)]
+    pub strategy: SummaryStrategy,
+    /// Text of the paragraph that ends the summary, for the `marker` strategy
+    #[serde(default = "default_summary_marker")]
+    pub marker: String,
+    /// Number of words of the summary, for the `words` strategy
+    #[serde(default = "default_summary_words")]
+    pub words: usize,
+    /// Maximum length of the description, in characters. Not used by the `before_h1` strategy.
+    #[serde(default = "default_description_length")]
+    pub description_length: usize,
+}
+
+impl Default for SummaryConfig {
+    fn default() -> Self {
+        SummaryConfig {
+            strategy: SummaryStrategy::default(),
+            marker: default_summary_marker(),
+            words: default_summary_words(),
+            description_length: default_description_length(),
+        }
+    }
+}
+
+fn default_summary_marker() -> String {
+    "<!--more-->".to_string()
+}
+
+fn default_summary_words() -> usize {
+    50
+}
+
+fn default_description_length() -> usize {
+    160
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SummaryStrategy {
+    /// The text before the `<h1>`, for both the summary and the description
+    #[default]
+    BeforeH1,
+    /// The HTML before the `<h1>` for the summary, and its text for the description
+    BeforeH1Html,
+    /// The `summary` ToC column
+    Column,
+    /// The HTML before the marker paragraph, which is removed
+    Marker,
+    /// The first words of the page
+    Words,
+}
+
+/// Page metadata read from Drive for GDocs. Nothing is read by default.
+#[derive(Deserialize, Debug, Default)]
+pub struct DriveMetadataConfig {
+    /// Set `lastmod` to the modification time of the doc when the `update_date` column is empty
+    #[serde(default)]
+    pub lastmod: bool,
+    /// Set `author` to the creator of the doc when the `author` column is empty. Takes precedence
+    /// over `default_author`.
+    #[serde(default)]
+    pub author: bool,
+    /// Set `contributors` to the people who edited the doc, in order of their first edit
+    #[serde(default)]
+    pub contributors: bool,
+    /// Public names of Google accounts, by email address or display name
+    #[serde(default)]
+    pub people: BTreeMap<String, String>,
+    /// Leave out the accounts that are not in `people`, instead of using their display name
+    #[serde(default)]
+    pub mapped_only: bool,
+}
+
+impl DriveMetadataConfig {
+    pub fn enabled(&self) -> bool {
+        self.lastmod || self.author || self.contributors
+    }
+}
+
+/// Handling of suggestions (proposed edits) in GDocs.
+#[derive(Deserialize, Debug, Default)]
+pub struct SuggestionsConfig {
+    #[serde(default)]
+    pub view_mode: SuggestionsViewMode,
+    /// Refuse to publish docs that have open suggestions
+    #[serde(default)]
+    pub refuse_open: bool,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SuggestionsViewMode {
+    /// The doc without its suggestions
+    #[default]
+    PreviewWithoutSuggestions,
+    /// The doc with all its suggestions accepted
+    PreviewWithSuggestions,
+}
+
+impl SuggestionsViewMode {
+    /// Value of the Docs API `suggestionsViewMode` parameter
+    pub fn api_value(&self) -> &'static str {
+        match self {
+            SuggestionsViewMode::PreviewWithoutSuggestions => "PREVIEW_WITHOUT_SUGGESTIONS",
+            SuggestionsViewMode::PreviewWithSuggestions => "PREVIEW_SUGGESTIONS_ACCEPTED",
+        }
+    }
+}
+
+/// Data files generated from `/#data/<name>` ToC rows.
+#[derive(Deserialize, Debug)]
+pub struct DataConfig {
+    /// Output directory, relative to the Hugo site
+    #[serde(default = "default_data_dir")]
+    pub dir: PathBuf,
+    #[serde(default)]
+    pub format: DataFormat,
+    /// Types of data columns, which are strings by default
+    #[serde(default)]
+    pub column_types: BTreeMap<String, ColumnType>,
+}
+
+impl Default for DataConfig {
+    fn default() -> Self {
+        DataConfig {
+            dir: default_data_dir(),
+            format: DataFormat::default(),
+            column_types: BTreeMap::new(),
+        }
+    }
+}
+
+fn default_data_dir() -> PathBuf {
+    "data".into()
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DataFormat {
+    #[default]
+    Yaml,
+    Json,
+    Toml,
+}
+
+/// Multilingual sites. The language of a page is given by the `lang` ToC column, and pages that
+/// are translations of each other have the same `translation_key`.
+#[derive(Deserialize, Debug, Default)]
+pub struct LanguagesConfig {
+    /// Language of the pages that have no `lang`. Pages have no language if not set.
+    pub default: Option<String>,
+    #[serde(default)]
+    pub layout: LanguageLayout,
+}
+
+/// See https://gohugo.io/content-management/multilingual/#translate-your-content
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LanguageLayout {
+    /// `index.<lang>.html`, no suffix for the default language
+    #[default]
+    Filenames,
+    /// `content/<lang>/...`, which requires a `contentDir` for each language in Hugo's config
+    Dirs,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ColumnType {
+    #[default]
+    String,
+    /// TRUE or FALSE
+    Bool,
+    Int,
+    Float,
+    /// Converted to RFC 3339 like `publish_date`
+    Date,
+    /// Comma or semicolon separated list of strings
+    List,
+    Json,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TocReader {
+    /// Sheets API, with typed values. Falls back to the CSV export if it fails.
+    #[default]
+    Api,
+    /// CSV export of the spreadsheet's first tab
+    Csv,
+}
+
+/// How text and paragraph styles (colors, underline, small caps, alignment) are rendered.
+#[derive(Deserialize, Debug)]
+pub struct StylingConfig {
+    #[serde(default)]
+    pub mode: StylingMode,
+    /// Prefix of generated class names
+    #[serde(default = "default_class_prefix")]
+    pub class_prefix: String,
+    #[serde(default)]
+    pub stylesheet: StylesheetScope,
+    /// Site-wide stylesheet location, relative to the Hugo site dir
+    #[serde(default = "default_stylesheet_path")]
+    pub stylesheet_path: PathBuf,
+    /// Known colors (`#rrggbb`) mapped to semantic names. A text with such a color gets the
+    /// `<prefix><name>` class, and `<prefix><name>-bg` for a background color.
+    #[serde(default)]
+    pub palette: BTreeMap<String, String>,
+}
+
+impl Default for StylingConfig {
+    fn default() -> Self {
+        StylingConfig {
+            mode: StylingMode::default(),
+            class_prefix: default_class_prefix(),
+            stylesheet: StylesheetScope::default(),
+            stylesheet_path: default_stylesheet_path(),
+            palette: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StylingMode {
+    /// Inline `style` attributes
+    #[default]
+    Inline,
+    /// Generated class names, with rules written to a stylesheet
+    Classes,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StylesheetScope {
+    /// Rules used by a page are added to its front matter's `inline_style`
+    #[default]
+    Page,
+    /// Rules of all pages are written to a single stylesheet
+    Site,
+}
+
+fn default_class_prefix() -> String {
+    "gd-".into()
+}
+
+fn default_stylesheet_path() -> PathBuf {
+    "static/css/gdocs.css".into()
+}
+
+/// Allowlist used to sanitize HTML injected by authors and link URLs. Documents marked as
+/// trusted in the table of contents are not sanitized.
+#[derive(Deserialize, Debug)]
+pub struct SanitizeConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    #[serde(default = "default_allowed_tags")]
+    pub tags: BTreeSet<String>,
+    /// Allowed attributes, on all tags. A trailing `*` matches a prefix, e.g. `data-*`.
+    #[serde(default = "default_allowed_attributes")]
+    pub attributes: BTreeSet<String>,
+    #[serde(default = "default_url_schemes")]
+    pub url_schemes: BTreeSet<String>,
+}
+
+impl Default for SanitizeConfig {
+    fn default() -> Self {
+        SanitizeConfig {
+            enabled: true,
+            tags: default_allowed_tags(),
+            attributes: default_allowed_attributes(),
+            url_schemes: default_url_schemes(),
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn string_set(values: &[&str]) -> BTreeSet<String> {
+    values.iter().map(|s| s.to_string()).collect()
+}
+
+fn default_allowed_tags() -> BTreeSet<String> {
+    string_set(&[
+        "a", "abbr", "article", "aside", "audio", "b", "blockquote", "br", "caption", "cite", "code",
+        "col", "colgroup", "dd", "del", "details", "div", "dl", "dt", "em", "figcaption", "figure",
+        "footer", "h1", "h2", "h3", "h4", "h5", "h6", "header", "hr", "i", "img", "ins", "kbd", "li",
+        "mark", "nav", "ol", "p", "picture", "pre", "q", "s", "section", "small", "source", "span",
+        "strong", "sub", "summary", "sup", "table", "tbody", "td", "tfoot", "th", "thead", "time",
+        "tr", "track", "u", "ul", "video",
+    ])
+}
+
+/// `style` and `id` are not allowed by default: they would let authors restyle or cover the page,
+/// and clobber ids used by the site.
+fn default_allowed_attributes() -> BTreeSet<String> {
+    string_set(&[
+        "alt", "aria-*", "class", "colspan", "controls", "data-*", "datetime", "height", "href",
+        "loading", "rel", "role", "rowspan", "src", "srcset", "target", "title", "type", "width",
+    ])
+}
+
+fn default_url_schemes() -> BTreeSet<String> {
+    string_set(&["http", "https", "mailto", "tel"])
+}
+
+/// Attributes added to external links.
+///
+/// Domain patterns are either a domain name, or `*.` followed by a domain name to match this
+/// domain and all its subdomains.
+#[derive(Deserialize, Debug)]
+pub struct LinksConfig {
+    /// Domains of links that aren't considered as external
+    #[serde(default)]
+    pub internal_domains: Vec<String>,
+    /// Rules for specific domains. The first matching rule is used.
+    #[serde(default)]
+    pub rules: Vec<LinkRule>,
+    /// Rule used for external links that match no other rule
+    #[serde(default = "default_link_rule")]
+    pub default: LinkRule,
+    /// What to do with links to GDocs that are not in the table of contents
+    #[serde(default)]
+    pub broken: BrokenLinkPolicy,
+}
+
+impl Default for LinksConfig {
+    fn default() -> Self {
+        LinksConfig {
+            internal_domains: Vec::new(),
+            rules: Vec::new(),
+            default: default_link_rule(),
+            broken: BrokenLinkPolicy::default(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BrokenLinkPolicy {
+    /// Pages with broken links are not published, and publication fails.
+    #[default]
+    Error,
+    /// The link is removed, keeping its text.
+    WarnUnlink,
+    /// The link is kept as is.
+    WarnKeep,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct LinkRule {
+    #[serde(default)]
+    pub domains: Vec<String>,
+    /// Link target. `per_domain` opens each domain in its own window.
+    pub target: Option<String>,
+    /// Value of the `rel` attribute, e.g. "noopener noreferrer nofollow sponsored"
+    pub rel: Option<String>,
+    /// Classes added to the link
+    pub class: Option<String>,
+    /// Class of an empty `<span>` added at the end of the link, to display an icon.
+    pub icon: Option<String>,
+}
+
+/// Links to Drive files, Google Sheets and Slides. Files whose mime type is listed in `download`
+/// are downloaded in the page bundle, and links are rewritten to this local copy. Other links
+/// are kept.
+#[derive(Deserialize, Debug)]
+pub struct DriveLinksConfig {
+    #[serde(default)]
+    pub download: BTreeSet<String>,
+    /// Export format of Google files (Sheets, Slides, etc), which cannot be downloaded as is.
+    #[serde(default = "default_drive_exports")]
+    pub export: BTreeMap<String, String>,
+}
+
+impl Default for DriveLinksConfig {
+    fn default() -> Self {
+        DriveLinksConfig {
+            download: BTreeSet::new(),
+            export: default_drive_exports(),
+        }
+    }
+}
+
+fn default_drive_exports() -> BTreeMap<String, String> {
+    maplit::btreemap! {
+        "application/vnd.google-apps.presentation".to_string() => "application/pdf".to_string(),
+        "application/vnd.google-apps.spreadsheet".to_string() => "application/pdf".to_string(),
+        "application/vnd.google-apps.drawing".to_string() => "application/pdf".to_string(),
+    }
+}
+
+pub const PER_DOMAIN_TARGET: &str = "per_domain";
+
+fn default_link_rule() -> LinkRule {
+    LinkRule {
+        target: Some(PER_DOMAIN_TARGET.to_string()),
+        ..LinkRule::default()
+    }
+}
+
 impl Config {
     pub fn read(path: &Path) -> anyhow::Result<Config> {
         let file = File::open(path).with_context(|| format!("Failed to open {:?}", path))?;
@@ -0,0 +1,199 @@
+//! Hugo data files generated from spreadsheets, for ToC rows with a `/#data/<name>` slug.
+//!
+//! The first row of a tab contains column names, and the second one human-readable names that
+//! are ignored. If there's an `id` column, the data file is an object with rows keyed by id,
+//! otherwise it's an array of rows. Dotted column names (`price.amount`) are nested keys.
+//! Columns without a type in `column_types` keep the text displayed in the spreadsheet, like
+//! "2.50 €" or "15%".
+//!
+//! The `data_tabs` ToC column selects the tabs to export: the first tab if it's empty, which is
+//! written to `<data dir>/<name>.<ext>`, or some named tabs (`*` for all of them) that are written
+//! to `<data dir>/<name>/<tab>.<ext>`.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::Value;
+use crate::config::{ColumnType, Config, DataFormat};
+use crate::gdocs_site::{self, DocData};
+use crate::publish::HyperC;
+use crate::sheets::{self, Tab};
+
+pub const DATA_SLUG_PREFIX: &str = "/#data/";
+
+pub fn publish_data_sheet(doc: &DocData, gdrive: &google_drive3::DriveHub<HyperC>, gdocs_api: &google_docs1::Docs<HyperC>, config: &Config) -> Result<()> {
+    let name = &doc.slug[DATA_SLUG_PREFIX.len()..];
+
+    let sheet_id = doc.gdoc_url.as_deref()
+        .and_then(gdocs_site::get_doc_id)
+        .ok_or_else(|| anyhow!("Failed to extract doc id from {:?}", doc.gdoc_url))?;
+
+    let all_tabs = doc.data_tabs.iter().any(|tab| tab == "*");
+    let requested = if all_tabs { Vec::new() } else { doc.data_tabs.clone() };
+
+    let tabs = match sheets::read_tabs(gdocs_api, sheet_id, &requested) {
+        Ok(tabs) => tabs,
+        Err(err) if doc.data_tabs.is_empty() => {
+            // The CSV export only contains the first tab
+            println!("Warning: {} - failed to read data with the Sheets API, using CSV export: {:#}", doc.slug, err);
+            vec![csv_tab(gdrive, sheet_id).context("Failed to download data document")?]
+        },
+        Err(err) => return Err(err.context("Failed to download data document")),
+    };
+
+    let data_dir = config.hugo_site_dir.join(&config.data.dir);
+
+    if doc.data_tabs.is_empty() {
+        let tab = tabs.into_iter().next()
+            .ok_or_else(|| anyhow!("Data document {} has no tab", name))?;
+        write_data(&data_dir.join(name), &tab_data(&tab, config)?, config.data.format)?;
+    } else {
+        for tab in &tabs {
+            let path = data_dir.join(name).join(gdocs_site::slugify(&tab.title));
+            write_data(&path, &tab_data(tab, config)?, config.data.format)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The first tab of a spreadsheet, from its CSV export. Values are the displayed text of cells.
+fn csv_tab(gdrive: &google_drive3::DriveHub<HyperC>, sheet_id: &str) -> Result<Tab> {
+    let bytes = crate::publish::export_csv(gdrive, sheet_id)?;
+    let rows = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .from_reader(bytes.as_ref())
+        .into_records()
+        .collect::<csv::Result<Vec<_>>>()?;
+
+    Ok(Tab { title: String::new(), formatted: rows.clone(), rows })
+}
+
+/// Convert the rows of a tab to an object keyed by id, or an array if there's no `id` column.
+fn tab_data(tab: &Tab, config: &Config) -> Result<Value> {
+    let Some((headers, records)) = tab.rows.split_first() else {
+        return Ok(Value::Array(Vec::new()));
+    };
+    let has_id = headers.iter().any(|h| h == "id");
+
+    let mut by_id = serde_json::Map::new();
+    let mut rows = Vec::new();
+    let mut problems = Vec::new();
+
+    // First line after the header is the human-readable column names: skip it
+    for (idx, record) in records.iter().enumerate().skip(1) {
+        // String columns keep the text displayed in the spreadsheet
+        let formatted = tab.formatted.get(idx + 1).unwrap_or(record);
+
+        if record.iter().all(|value| value.is_empty()) {
+            continue;
+        }
+        let row_num = record.position().map_or(0, |pos| pos.record() + 1);
+
+        let mut id = None;
+        let mut row = BTreeMap::new();
+        for ((column, value), text) in headers.iter().zip(record.iter()).zip(formatted.iter()) {
+            if column.is_empty() {
+                continue;
+            }
+            if column == "id" {
+                id = Some(value.to_string());
+                continue;
+            }
+
+            let column_type = config.data.column_types.get(column).copied().unwrap_or_default();
+            let value = if column_type == ColumnType::String {
+                Value::String(text.to_string())
+            } else if value.is_empty() {
+                // Typed columns can't be empty strings
+                continue;
+            } else {
+                match gdocs_site::convert_value(value, column_type, config) {
+                    Ok(value) => value,
+                    Err(message) => {
+                        problems.push(format!("tab '{}', row {}, column '{}': {}", tab.title, row_num, column, message));
+                        continue;
+                    }
+                }
+            };
+
+            if let Err(message) = gdocs_site::insert_dotted(&mut row, column, value) {
+                problems.push(format!("tab '{}', row {}, column '{}': {}", tab.title, row_num, column, message));
+            }
+        }
+
+        let row = Value::Object(row.into_iter().collect());
+        if has_id {
+            by_id.insert(id.unwrap_or_default(), row);
+        } else {
+            rows.push(row);
+        }
+    }
+
+    if !problems.is_empty() {
+        bail!("{} problem(s) in data document:\n  - {}", problems.len(), problems.join("\n  - "));
+    }
+
+    Ok(if has_id { Value::Object(by_id) } else { Value::Array(rows) })
+}
+
+/// Write a data file. The extension is added to `path`.
+fn write_data(path: &Path, data: &Value, format: DataFormat) -> Result<()> {
+    let (extension, text) = match format {
+        DataFormat::Yaml => ("yml", serde_yaml::to_string(data)?),
+        DataFormat::Json => ("json", serde_json::to_string_pretty(data)?),
+        DataFormat::Toml => {
+            // A TOML document is a table: arrays of rows are in an `items` key
+            let data = match data {
+                Value::Array(_) => serde_json::json!({ "items": data }),
+                _ => data.clone(),
+            };
+            // toml::Value writes values before tables, as required by TOML
+            ("toml", toml::to_string(&toml::Value::try_from(data)?)?)
+        },
+    };
+
+    let path = path.with_extension(extension);
+    fs::create_dir_all(path.parent().unwrap())?;
+    fs::write(&path, text).with_context(|| format!("Cannot write to {:?}", &path))?;
+
+    println!("Saved data file to {:?}", path);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sheets::Tab;
+
+    #[test]
+    fn test_tab_data() {
+        let mut config: crate::config::Config = serde_yaml::from_str("hugo_site_dir: site").unwrap();
+        config.data.column_types.insert("price.amount".to_string(), crate::config::ColumnType::Float);
+
+        let rows = |csv: &str| csv::ReaderBuilder::new().has_headers(false).from_reader(csv.as_bytes()).into_records()
+            .collect::<csv::Result<Vec<_>>>().unwrap();
+
+        // Typed columns use cell values, string columns the displayed text
+        let tab = Tab {
+            title: "Products".to_string(),
+            rows: rows("name,price.amount,price.currency,discount\nName,Price,Currency,Discount\nTea,2.5,EUR,0.15\nCoffee,,,\n"),
+            formatted: rows("name,price.amount,price.currency,discount\nName,Price,Currency,Discount\nTea,2.50 €,EUR,15%\nCoffee,,,\n"),
+        };
+        assert_eq!(
+            serde_json::json!([
+                { "name": "Tea", "price": { "amount": 2.5, "currency": "EUR" }, "discount": "15%" },
+                { "name": "Coffee", "price": { "currency": "" }, "discount": "" },
+            ]),
+            super::tab_data(&tab, &config).unwrap()
+        );
+
+        let tab = Tab {
+            title: "Products".to_string(),
+            rows: rows("id,price.amount\nId,Price\ntea,cheap\n"),
+            formatted: Vec::new(),
+        };
+        assert!(super::tab_data(&tab, &config).unwrap_err().to_string()
+            .contains("tab 'Products', row 3, column 'price.amount': Expecting a number, got cheap"));
+    }
+}
@@ -0,0 +1,265 @@
+//! Page metadata read from Drive for GDocs: last modification time, creator and contributors, and
+//! the revision to publish for docs that have a `revision` in the ToC.
+//!
+//! Google accounts are turned into public names with the `people` mapping of the config, and
+//! otherwise use their display name (unless `mapped_only` is set).
+
+use std::collections::HashMap;
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, FixedOffset, Utc};
+use google_drive3::api::Scope;
+use itertools::Itertools;
+use crate::config::{Config, DriveMetadataConfig};
+use crate::publish::HyperC;
+
+/// A Google account.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Person {
+    pub name: Option<String>,
+    pub email: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Revision {
+    pub id: String,
+    pub modified_time: Option<DateTime<Utc>>,
+    pub user: Option<Person>,
+    /// Is this revision published to the web?
+    pub published: bool,
+    /// Export URLs, by mime type
+    pub export_links: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DocMetadata {
+    pub modified_time: Option<DateTime<Utc>>,
+    pub last_modifying_user: Option<Person>,
+    /// Revisions, oldest first. Only listed if requested.
+    pub revisions: Vec<Revision>,
+}
+
+/// Source of Drive metadata. Implemented by the Drive API client, and by mocks in tests.
+pub trait MetadataSource {
+    fn doc_metadata(&self, file_id: &str, with_revisions: bool) -> Result<DocMetadata>;
+}
+
+impl MetadataSource for google_drive3::DriveHub<HyperC> {
+    fn doc_metadata(&self, file_id: &str, with_revisions: bool) -> Result<DocMetadata> {
+        let rt = tokio::runtime::Handle::current();
+
+        let (_, file) = rt.block_on(self.files().get(file_id)
+            .param("fields", "modifiedTime,lastModifyingUser(displayName,emailAddress)")
+            .supports_all_drives(true)
+            .add_scope(Scope::Readonly)
+            .doit())
+            .with_context(|| format!("Failed to get Drive metadata of {}", file_id))?;
+
+        let mut revisions = Vec::new();
+        if with_revisions {
+            let mut page_token: Option<String> = None;
+            loop {
+                let mut call = self.revisions().list(file_id)
+                    .param("fields", "nextPageToken,revisions(id,modifiedTime,published,exportLinks,lastModifyingUser(displayName,emailAddress))")
+                    .page_size(1000)
+                    .add_scope(Scope::Readonly);
+                if let Some(token) = &page_token {
+                    call = call.page_token(token);
+                }
+
+                let (_, list) = rt.block_on(call.doit())
+                    .with_context(|| format!("Failed to list revisions of {}", file_id))?;
+
+                revisions.extend(list.revisions.unwrap_or_default().into_iter().map(|rev| Revision {
+                    id: rev.id.unwrap_or_default(),
+                    modified_time: rev.modified_time,
+                    user: rev.last_modifying_user.map(person),
+                    published: rev.published.unwrap_or_default(),
+                    export_links: rev.export_links.unwrap_or_default(),
+                }));
+
+                page_token = list.next_page_token;
+                if page_token.is_none() {
+                    break;
+                }
+            }
+        }
+
+        Ok(DocMetadata {
+            modified_time: file.modified_time,
+            last_modifying_user: file.last_modifying_user.map(person),
+            revisions,
+        })
+    }
+}
+
+fn person(user: google_drive3::api::User) -> Person {
+    Person {
+        name: user.display_name,
+        email: user.email_address,
+    }
+}
+
+/// Front matter values computed from Drive metadata, according to the config.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct PageMetadata {
+    pub lastmod: Option<DateTime<FixedOffset>>,
+    pub author: Option<String>,
+    pub contributors: Vec<String>,
+}
+
+/// Get the metadata of a doc. Drive isn't called if the config doesn't use it.
+pub fn page_metadata(source: &impl MetadataSource, file_id: &str, config: &Config) -> Result<PageMetadata> {
+    let cfg = &config.drive_metadata;
+    if !cfg.enabled() {
+        return Ok(PageMetadata::default());
+    }
+
+    let metadata = source.doc_metadata(file_id, cfg.author || cfg.contributors)?;
+
+    let lastmod = metadata.modified_time
+        .filter(|_| cfg.lastmod)
+        .map(|time| time.with_timezone(&config.timezone).fixed_offset());
+
+    // The creator of the doc is the author of its first revision
+    let author = metadata.revisions.first()
+        .and_then(|rev| rev.user.as_ref())
+        .or(metadata.last_modifying_user.as_ref())
+        .filter(|_| cfg.author)
+        .and_then(|person| public_name(person, cfg));
+
+    let contributors = if cfg.contributors {
+        metadata.revisions.iter()
+            .filter_map(|rev| rev.user.as_ref())
+            .filter_map(|person| public_name(person, cfg))
+            .unique()
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    Ok(PageMetadata { lastmod, author, contributors })
+}
+
+/// A revision to publish instead of the live doc.
+#[derive(Debug, PartialEq, Eq)]
+pub struct PinnedRevision {
+    pub id: String,
+    /// URL of the revision's HTML export
+    pub html_url: String,
+    /// Time of the last edit, if the doc has been edited since this revision
+    pub edited_since: Option<DateTime<Utc>>,
+}
+
+/// Find the revision to publish for a `revision` ToC value: a revision id, or `published` for the
+/// last revision published to the web.
+pub fn pinned_revision(source: &impl MetadataSource, file_id: &str, revision: &str) -> Result<PinnedRevision> {
+    let metadata = source.doc_metadata(file_id, true)?;
+
+    let pinned = if revision == "published" {
+        metadata.revisions.iter().rev().find(|rev| rev.published)
+            .ok_or_else(|| anyhow!("Doc {} has no published revision", file_id))?
+    } else {
+        metadata.revisions.iter().find(|rev| rev.id == revision)
+            .ok_or_else(|| anyhow!("Doc {} has no revision '{}'", file_id, revision))?
+    };
+
+    let html_url = pinned.export_links.get("text/html")
+        .ok_or_else(|| anyhow!("Revision '{}' of doc {} has no HTML export", pinned.id, file_id))?;
+
+    let last = metadata.revisions.last().unwrap();
+    let edited_since = if last.id != pinned.id { last.modified_time } else { None };
+
+    Ok(PinnedRevision {
+        id: pinned.id.clone(),
+        html_url: html_url.clone(),
+        edited_since,
+    })
+}
+
+/// Public name of a Google account, from the `people` mapping by email address or display name.
+fn public_name(person: &Person, cfg: &DriveMetadataConfig) -> Option<String> {
+    let mapped = person.email.as_ref()
+        .and_then(|email| cfg.people.iter().find(|(key, _)| key.eq_ignore_ascii_case(email)))
+        .or_else(|| person.name.as_ref().and_then(|name| cfg.people.get_key_value(name)))
+        .map(|(_, public_name)| public_name.clone());
+
+    if cfg.mapped_only {
+        mapped
+    } else {
+        mapped.or_else(|| person.name.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use super::{DocMetadata, MetadataSource, Person, PinnedRevision, Revision};
+
+    struct MockSource;
+
+    impl MetadataSource for MockSource {
+        fn doc_metadata(&self, _file_id: &str, with_revisions: bool) -> anyhow::Result<DocMetadata> {
+            let person = |name: &str, email: &str| Some(Person { name: Some(name.to_string()), email: Some(email.to_string()) });
+            let revision = |id: &str, day, user| Revision {
+                id: id.to_string(),
+                modified_time: Some(Utc.with_ymd_and_hms(2024, 3, day, 12, 0, 0).unwrap()),
+                user,
+                published: id == "2",
+                export_links: [("text/html".to_string(), format!("https://export/{}", id))].into(),
+            };
+
+            Ok(DocMetadata {
+                modified_time: Some(Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap()),
+                last_modifying_user: person("Bob", "bob@example.com"),
+                revisions: if with_revisions {
+                    vec![
+                        revision("1", 1, person("Jane Roe", "jane@example.com")),
+                        revision("2", 2, person("Bob", "bob@example.com")),
+                        revision("3", 3, person("Jane Roe", "JANE@example.com")),
+                        revision("4", 4, person("Anonymous", "anon@example.com")),
+                    ]
+                } else {
+                    Vec::new()
+                },
+            })
+        }
+    }
+
+    #[test]
+    fn test_page_metadata() {
+        let config: crate::config::Config = serde_yaml::from_str(indoc::indoc! {"
+            hugo_site_dir: site
+            timezone: Europe/Paris
+            drive_metadata:
+              lastmod: true
+              contributors: true
+              people:
+                jane@example.com: Jane
+                Bob: Robert
+        "}).unwrap();
+
+        let metadata = super::page_metadata(&MockSource, "abc", &config).unwrap();
+        assert_eq!("2024-03-01T13:00:00+01:00", metadata.lastmod.unwrap().to_rfc3339());
+        assert_eq!(None, metadata.author);
+        assert_eq!(vec!["Jane", "Robert", "Anonymous"], metadata.contributors);
+
+        let mut config = config;
+        config.drive_metadata.author = true;
+        config.drive_metadata.mapped_only = true;
+        let metadata = super::page_metadata(&MockSource, "abc", &config).unwrap();
+        assert_eq!(Some("Jane"), metadata.author.as_deref());
+        assert_eq!(vec!["Jane", "Robert"], metadata.contributors);
+    }
+
+    #[test]
+    fn test_pinned_revision() {
+        assert_eq!(PinnedRevision {
+            id: "2".to_string(),
+            html_url: "https://export/2".to_string(),
+            edited_since: Some(Utc.with_ymd_and_hms(2024, 3, 4, 12, 0, 0).unwrap()),
+        }, super::pinned_revision(&MockSource, "abc", "published").unwrap());
+
+        assert_eq!(None, super::pinned_revision(&MockSource, "abc", "4").unwrap().edited_since);
+        assert!(super::pinned_revision(&MockSource, "abc", "5").is_err());
+    }
+}
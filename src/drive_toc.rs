@@ -0,0 +1,181 @@
+//! Table of contents built from the hierarchy of a Drive folder.
+//!
+//! Sub-folders are site sections and the GDocs they contain are pages, with slugs derived from
+//! folder and file names. A GDoc named `index` or `_index` is the page of its folder.
+//!
+//! Other ToC columns can be set for each GDoc, either as Drive custom properties, or in the file
+//! description with one `column: value` per line. Custom properties take precedence.
+
+use std::collections::{BTreeMap, BTreeSet};
+use anyhow::{Context, Result};
+use google_drive3::api::Scope;
+use crate::config::Config;
+use crate::gdocs_site::{self, DocData};
+use crate::publish::HyperC;
+
+const FOLDER_TYPE: &str = "application/vnd.google-apps.folder";
+const DOCUMENT_TYPE: &str = "application/vnd.google-apps.document";
+
+/// A row of the table of contents, as column name -> value
+type TocRow = BTreeMap<String, String>;
+
+/// A ToC row, with a description of the Drive file it comes from for problem reports
+type SourcedRow = (String, TocRow);
+
+pub fn read_folder_toc(gdrive: &google_drive3::DriveHub<HyperC>, folder_url: &str, config: &Config) -> Result<Vec<DocData>> {
+    let folder_id = gdocs_site::get_folder_id(folder_url);
+
+    println!("Listing ToC from Drive folder id={}", folder_id);
+
+    let mut rows = Vec::new();
+    list_folder(gdrive, folder_id, "", &mut rows)?;
+
+    // Columns are all the keys found in rows
+    let columns = rows.iter()
+        .flat_map(|(_, row)| row.keys())
+        .collect::<BTreeSet<_>>();
+
+    let headers = columns.iter().collect::<csv::StringRecord>();
+    let records = rows.iter()
+        .map(|(_, row)| {
+            columns.iter()
+                .map(|col| row.get(*col).map_or("", |v| v.as_str()))
+                .collect::<csv::StringRecord>()
+        })
+        .collect::<Vec<_>>();
+
+    let sources = rows.iter().map(|(source, _)| source.clone()).collect::<Vec<_>>();
+
+    let docs = DocData::read_records_with_sources(&headers, &records, &sources, config)
+        .context("Problem reading ToC from Drive folder")?;
+
+    Ok(docs)
+}
+
+/// Add the rows for the GDocs in a folder and its sub-folders.
+fn list_folder(gdrive: &google_drive3::DriveHub<HyperC>, folder_id: &str, path: &str, rows: &mut Vec<SourcedRow>) -> Result<()> {
+    let rt = tokio::runtime::Handle::current();
+
+    let mut page_token: Option<String> = None;
+    loop {
+        let mut call = gdrive.files().list()
+            .q(&format!("'{}' in parents and trashed = false", folder_id))
+            .param("fields", "nextPageToken,files(id,name,mimeType,description,properties)")
+            .order_by("folder,name")
+            .supports_all_drives(true)
+            .include_items_from_all_drives(true)
+            .add_scope(Scope::Readonly);
+        if let Some(token) = &page_token {
+            call = call.page_token(token);
+        }
+
+        let (_, list) = rt.block_on(call.doit())
+            .with_context(|| format!("Failed to list Drive folder {}", folder_id))?;
+
+        for file in list.files.unwrap_or_default() {
+            let (Some(id), Some(name)) = (&file.id, &file.name) else {
+                continue;
+            };
+
+            match file.mime_type.as_deref() {
+                Some(FOLDER_TYPE) => {
+                    let sub_path = format!("{}/{}", path, gdocs_site::slugify(name));
+                    list_folder(gdrive, id, &sub_path, rows)?;
+                },
+                Some(DOCUMENT_TYPE) => {
+                    let slug = if name == "index" || name == "_index" {
+                        if path.is_empty() { "/".to_string() } else { path.to_string() }
+                    } else {
+                        format!("{}/{}", path, gdocs_site::slugify(name))
+                    };
+
+                    let row = doc_row(id, name, slug, file.description.as_deref(), file.properties.unwrap_or_default());
+                    rows.push((format!("Drive file '{}' (id={})", name, id), row));
+                },
+                _ => (),
+            }
+        }
+
+        page_token = list.next_page_token;
+        if page_token.is_none() {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// The ToC row of a GDoc. Custom properties override values from the description.
+fn doc_row(id: &str, name: &str, slug: String, description: Option<&str>, properties: impl IntoIterator<Item = (String, String)>) -> TocRow {
+    let mut row = TocRow::new();
+    row.insert("title".to_string(), name.to_string());
+    row.insert("slug".to_string(), slug);
+    row.insert("publish".to_string(), "TRUE".to_string());
+    row.insert("gdoc_url".to_string(), format!("https://docs.google.com/document/d/{}/edit", id));
+
+    if let Some(description) = description {
+        row.extend(parse_description(description));
+    }
+    row.extend(properties);
+
+    row
+}
+
+/// Parse `column: value` lines in a file description. Other lines are ignored.
+fn parse_description(description: &str) -> TocRow {
+    description.lines()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.trim(), value.trim()))
+        .filter(|(key, _)| !key.is_empty() && !key.contains(' '))
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_parse_description() {
+        let row = super::parse_description(indoc::indoc! {"
+            Notes about this page: not a column
+            weight: 2
+             category :  News
+            no separator here
+            : no key
+            summary: Time: 10am
+        "});
+
+        assert_eq!(3, row.len());
+        assert_eq!("2", row["weight"]);
+        assert_eq!("News", row["category"]);
+        assert_eq!("Time: 10am", row["summary"]);
+    }
+
+    #[test]
+    fn test_properties_override_description() {
+        let properties = [
+            ("weight".to_string(), "5".to_string()),
+            ("publish".to_string(), "FALSE".to_string()),
+        ];
+        let row = super::doc_row("abc", "Intro", "/intro".to_string(), Some("weight: 2\nlang: fr"), properties);
+
+        assert_eq!("Intro", row["title"]);
+        assert_eq!("/intro", row["slug"]);
+        assert_eq!("https://docs.google.com/document/d/abc/edit", row["gdoc_url"]);
+        assert_eq!("5", row["weight"]);
+        assert_eq!("fr", row["lang"]);
+        assert_eq!("FALSE", row["publish"]);
+    }
+
+    #[test]
+    fn test_problems_name_drive_files() {
+        let config = serde_yaml::from_str("hugo_site_dir: site").unwrap();
+        let row = super::doc_row("abc", "Intro", "/intro".to_string(), None, [("publish".to_string(), "yes".to_string())]);
+
+        let headers = row.keys().collect::<csv::StringRecord>();
+        let records = vec![row.values().collect::<csv::StringRecord>()];
+        let sources = vec!["Drive file 'Intro' (id=abc)".to_string()];
+
+        let err = crate::gdocs_site::DocData::read_records_with_sources(&headers, &records, &sources, &config).unwrap_err();
+        assert!(format!("{:#}", err).contains("Drive file 'Intro' (id=abc), column 'publish': Expecting TRUE or FALSE"), "{:#}", err);
+    }
+}
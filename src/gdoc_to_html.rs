@@ -9,7 +9,11 @@ use std::ops::AddAssign;
 use std::path::Path;
 use google_docs1::api as docs;
 use crate::html::HtmlConsumer;
+use crate::config::{SanitizeConfig, StylingConfig, StylingMode};
+use crate::sanitize;
+use crate::styles::{self, StyleSheet};
 use anyhow::{anyhow, bail};
+use itertools::Itertools;
 
 
 pub fn read(p: impl AsRef<Path>) -> anyhow::Result<docs::Document> {
@@ -163,29 +167,64 @@ impl Display for InlineAttributes {
 // Callbacks and lifetimes https://stackoverflow.com/questions/41081240/idiomatic-callbacks-in-rust
 
 
+#[derive(Default)]
+pub struct RenderOptions<'a> {
+    /// Text and paragraph styling. Inline styles if not set.
+    pub styling: Option<&'a StylingConfig>,
+    /// Sanitization of author-injected HTML and link URLs. No sanitization if not set.
+    pub sanitize: Option<&'a SanitizeConfig>,
+}
+
+pub struct Rendered {
+    pub html: String,
+    /// CSS rules for the classes used in `html`. Empty when using inline styles.
+    pub styles: StyleSheet,
+    /// Content that was removed by sanitization.
+    pub warnings: Vec<String>,
+}
+
 pub fn render(
     doc: &docs::Document,
     ) -> anyhow::Result<String> {
+    Ok(render_with(doc, &RenderOptions::default())?.html)
+}
+
+pub fn render_with(
+    doc: &docs::Document,
+    options: &RenderOptions,
+    ) -> anyhow::Result<Rendered> {
     let mut renderer = HtmlRenderer {
         doc,
+        styling: options.styling,
+        sanitize: options.sanitize,
         html: String::new(),
+        styles: StyleSheet::default(),
+        warnings: Vec::new(),
         tags: Vec::new(),
         last_is_nl: false,
     };
 
     renderer.format_doc()?;
-    return Ok(renderer.html);
+    return Ok(Rendered {
+        html: renderer.html,
+        styles: renderer.styles,
+        warnings: renderer.warnings,
+    });
 }
 
 struct HtmlRenderer <'a> {
     // Input
     doc: &'a docs::Document,
+    styling: Option<&'a StylingConfig>,
+    sanitize: Option<&'a SanitizeConfig>,
 
     // State
     tags: Vec<&'a str>,
 
     // Output
     html: String,
+    styles: StyleSheet,
+    warnings: Vec<String>,
 
     last_is_nl: bool,
 }
@@ -289,10 +328,23 @@ impl <'a> HtmlRenderer<'a> {
         }
     }
 
+    /// Get the `class` and `style` attribute values for an element, depending on the styling mode:
+    /// with class-based styling, style declarations are replaced by a generated class.
+    fn style_attrs(&mut self, mut classes: Vec<String>, style_attr: String) -> (String, String) {
+        match self.styling {
+            Some(styling) if styling.mode == StylingMode::Classes && !style_attr.is_empty() => {
+                classes.push(self.styles.class_for(styling, &style_attr));
+                (classes.join(" "), String::new())
+            },
+            _ => (classes.join(" "), style_attr),
+        }
+    }
+
     /// Result should be escaped, and safe for inclusion in html
     fn convert_url(&self, url: impl AsRef<str>) -> String {
-        // TODO
-        url.as_ref().to_string()
+        // URLs are written in single-quoted attributes. '&' is escaped so that character
+        // references in the URL are not decoded by browsers.
+        url.as_ref().replace('&', "&amp;").replace('\'', "&#39;")
     }
 
     fn format_doc(&mut self) -> anyhow::Result<()> {
@@ -409,10 +461,7 @@ impl <'a> HtmlRenderer<'a> {
             }
         }
 
-        let trimmed = text.trim();
-
-        if (trimmed.starts_with("{{") && trimmed.ends_with("}}")) ||
-            (trimmed.starts_with("{:") && trimmed.ends_with(":}")) {
+        if is_shortcode(&text) {
             Some(text)
         } else {
             None
@@ -515,7 +564,14 @@ impl <'a> HtmlRenderer<'a> {
                 return Ok(());
             } else {
                 self.nl();
-                Self::write_shortcode(&short_code, &mut self.html)?;
+                if let Some(sanitize) = self.sanitize {
+                    let mut html = String::new();
+                    Self::write_shortcode(&short_code, &mut html)?;
+                    let html = sanitize::clean_html(sanitize, &html, &mut self.warnings);
+                    *self += html;
+                } else {
+                    Self::write_shortcode(&short_code, &mut self.html)?;
+                }
                 self.nl();
                 return Ok(());
             }
@@ -527,7 +583,7 @@ impl <'a> HtmlRenderer<'a> {
         // - para.suggested_positioned_object_ids
 
         let mut tag = "p";
-        let mut class: &'a str = "";
+        let mut classes = Vec::<String>::new();
         let mut id = "";
 
         // para.positioned_object_ids
@@ -541,7 +597,7 @@ impl <'a> HtmlRenderer<'a> {
                     "HEADING_2" => tag = "h2",
                     "HEADING_3" => tag = "h3",
                     "HEADING_4" => tag = "h4",
-                    _ => class = name, // "TITLE" & "SUBTITLE"
+                    _ => classes.push(name.clone()), // "TITLE" & "SUBTITLE"
                 }
             }
             if let Some(align) = &style.alignment {
@@ -563,8 +619,10 @@ impl <'a> HtmlRenderer<'a> {
             tag = "li";
         }
 
+        let (class_attr, style_attr) = self.style_attrs(classes, style_attr);
+
         self.nl();
-        self.start_tag(tag, &[("id", id), ("class", class), ("style", &style_attr)]);
+        self.start_tag(tag, &[("id", id), ("class", &class_attr), ("style", &style_attr)]);
 
         if let Some(elements) = &para.elements {
             for elt in elements {
@@ -691,6 +749,7 @@ impl <'a> HtmlRenderer<'a> {
 
     fn format_text_run(&mut self, text: &docs::TextRun) {
         let mut style_attr = String::new();
+        let mut classes = Vec::<String>::new();
         let mut elts = Vec::<&str>::new();
 
         let mut link: Option<String> = None;
@@ -740,9 +799,9 @@ impl <'a> HtmlRenderer<'a> {
             }
 
             if link.is_none() {
-                add_color("color", &mut style_attr, &style.foreground_color);
+                self.add_color("color", &mut style_attr, &mut classes, &style.foreground_color);
             }
-            add_color("background-color", &mut style_attr, &style.background_color);
+            self.add_color("background-color", &mut style_attr, &mut classes, &style.background_color);
 
             // FIXME
             // - style.weighted_font_family
@@ -750,6 +809,13 @@ impl <'a> HtmlRenderer<'a> {
             // - style.font_size
         }
 
+        if let (Some(sanitize), Some(url)) = (self.sanitize, &link) {
+            if !url.starts_with('#') && !sanitize::is_safe_url(sanitize, url) {
+                self.warnings.push(format!("Removed link with forbidden URL '{}'", url));
+                link = None;
+            }
+        }
+
         // Start <a>
         if let Some(ref url) = link {
             *self += "<a href='";
@@ -769,8 +835,10 @@ impl <'a> HtmlRenderer<'a> {
         // Start <span> if we have custom styles. We do not add style on a surrounding tag, as it
         // may conflict with that tag's default styling (e.g. strike-through in <del> may be
         // overriden by an underlined style)
-        if style_attr.len() > 0 {
-            self.start_tag("span", &[("style", &style_attr)]);
+        let (class_attr, style_attr) = self.style_attrs(classes, style_attr);
+        let has_span = !class_attr.is_empty() || !style_attr.is_empty();
+        if has_span {
+            self.start_tag("span", &[("class", &class_attr), ("style", &style_attr)]);
         }
 
         // Content
@@ -779,7 +847,7 @@ impl <'a> HtmlRenderer<'a> {
         }
 
         // Close <span>
-        if style_attr.len() > 0 {
+        if has_span {
             self.end_tag();
         }
 
@@ -873,31 +941,50 @@ impl <'a> HtmlRenderer<'a> {
 
     }
 
-}
-
-fn add_color(name: &str, style_attr: &mut String, color: &Option<docs::OptionalColor>) {
-    // Optional color is a weird russian puppet. And also:
-    // > If set, this will be used as an opaque color. If unset, this represents a
-    // > transparent color.
-    // -- What is the transparent color if we don't have rgba information?
-    if let Some(color) = &color {
-        if let Some(color) = &color.color {
-            if let Some(color) = &color.rgb_color {
-                // End of russian puppets...
-                let style = format!(
-                    "{}:rgb({:.0}%,{:.0}%,{:.0}%);",
-                    name,
+    /// Adds a color style declaration, or the corresponding class if it's a known palette color.
+    fn add_color(&self, name: &str, style_attr: &mut String, classes: &mut Vec<String>, color: &Option<docs::OptionalColor>) {
+        // Optional color is a weird russian puppet. And also:
+        // > If set, this will be used as an opaque color. If unset, this represents a
+        // > transparent color.
+        // -- What is the transparent color if we don't have rgba information?
+        if let Some(color) = &color {
+            if let Some(color) = &color.color {
+                if let Some(color) = &color.rgb_color {
+                    // End of russian puppets...
                     // Some or all colors are sometimes null.
-                    color.red.unwrap_or(0.0)*100.0,
-                    color.green.unwrap_or(0.0)*100.0,
-                    color.blue.unwrap_or(0.0)*100.0
-                );
-                *style_attr += &style;
+                    let red = color.red.unwrap_or(0.0);
+                    let green = color.green.unwrap_or(0.0);
+                    let blue = color.blue.unwrap_or(0.0);
+
+                    if let Some(styling) = self.styling {
+                        if let Some(palette_name) = styles::palette_name(styling, red, green, blue) {
+                            let suffix = if name == "color" { "" } else { "-bg" };
+                            classes.push(format!("{}{}{}", styling.class_prefix, palette_name, suffix));
+                            return;
+                        }
+                    }
+
+                    let style = format!(
+                        "{}:rgb({:.0}%,{:.0}%,{:.0}%);",
+                        name,
+                        red*100.0,
+                        green*100.0,
+                        blue*100.0
+                    );
+                    *style_attr += &style;
+                }
             }
         }
     }
 }
 
+/// Is the text of a paragraph a Hugo shortcode or a gdoc2hugo attribute tag?
+fn is_shortcode(text: &str) -> bool {
+    let trimmed = text.trim();
+    (trimmed.starts_with("{{") && trimmed.ends_with("}}")) ||
+        (trimmed.starts_with("{:") && trimmed.ends_with(":}"))
+}
+
 fn dimension_to_px(dimension: &docs::Dimension) -> f64 {
     let unit = dimension.unit.as_ref().unwrap();
     let magnitude = dimension.magnitude.as_ref().unwrap();
@@ -907,6 +994,208 @@ fn dimension_to_px(dimension: &docs::Dimension) -> f64 {
     panic!("Unknown unit {}", unit);
 }
 
+//-------------------------------------------------------------------------------------------------
+// Drive HTML exports, used for doc revisions that the Docs API doesn't provide
+
+/// Properties of the export's class rules that are kept: those that are also rendered from the
+/// doc JSON. Fonts, sizes, spacing, etc. are left to the site's theme.
+const EXPORT_PROPERTIES: &[&str] = &[
+    "font-weight", "font-style", "text-decoration", "font-variant", "vertical-align", "color",
+    "background-color", "text-align",
+];
+
+/// Values of the export's class rules that are the default text style, and are not kept.
+const EXPORT_DEFAULTS: &[&str] = &[
+    "font-weight:400", "font-style:normal", "text-decoration:none", "font-variant:normal",
+    "vertical-align:baseline", "color:#000000", "background-color:#ffffff", "text-align:left",
+];
+
+const VOID_ELEMENTS: &[&str] = &["area", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track", "wbr"];
+
+/// Render the body of a doc's HTML export (e.g. of a revision) like `render_with` renders the doc
+/// JSON: shortcode paragraphs are converted, and the styles of the export's generated classes
+/// (`c0`, `c1`, etc.) are rendered according to the styling options. Links are unwrapped from
+/// Google redirects.
+pub fn render_export(html: &str, options: &RenderOptions) -> anyhow::Result<Rendered> {
+    let document = scraper::Html::parse_document(html);
+
+    let style_selector = scraper::Selector::parse("style").unwrap();
+    let css = document.select(&style_selector).flat_map(|style| style.text()).collect::<String>();
+
+    let mut export = Export {
+        options,
+        class_styles: export_class_styles(&css),
+        html: String::new(),
+        styles: StyleSheet::default(),
+        warnings: Vec::new(),
+    };
+
+    // The body's classes are the page layout (size, margins): they're not kept
+    let body_selector = scraper::Selector::parse("body").unwrap();
+    if let Some(body) = document.select(&body_selector).next() {
+        export.write_children(body)?;
+    }
+
+    Ok(Rendered {
+        html: export.html,
+        styles: export.styles,
+        warnings: export.warnings,
+    })
+}
+
+/// Is this a class generated by the export, that is only meaningful with its stylesheet?
+fn is_export_class(class: &str) -> bool {
+    let class_re = lazy_regex::regex!(r"^(c[0-9]+|lst-kix_.*)$");
+    class_re.is_match(class)
+}
+
+/// Style declarations of the export's generated classes, keeping only `EXPORT_PROPERTIES`.
+fn export_class_styles(css: &str) -> HashMap<String, String> {
+    let class_re = lazy_regex::regex!(r"^\.(c[0-9]+)$");
+
+    css.split('}')
+        .filter_map(|rule| rule.split_once('{'))
+        .filter_map(|(selector, declarations)| {
+            let class = class_re.captures(selector.trim())?.get(1)?.as_str().to_string();
+            let declarations = declarations.split(';')
+                .filter_map(|decl| decl.split_once(':'))
+                .map(|(name, value)| format!("{}:{}", name.trim(), value.trim()))
+                .filter(|decl| {
+                    EXPORT_PROPERTIES.iter().any(|prop| decl.starts_with(&format!("{}:", prop)))
+                        && !EXPORT_DEFAULTS.contains(&decl.as_str())
+                })
+                .map(|decl| format!("{};", decl))
+                .collect::<String>();
+            Some((class, declarations))
+        })
+        .collect()
+}
+
+struct Export<'a> {
+    options: &'a RenderOptions<'a>,
+    class_styles: HashMap<String, String>,
+
+    html: String,
+    styles: StyleSheet,
+    warnings: Vec<String>,
+}
+
+impl Export<'_> {
+    fn write_children(&mut self, elt: scraper::ElementRef) -> anyhow::Result<()> {
+        for child in elt.children() {
+            if let Some(text) = child.value().as_text() {
+                crate::html::write_escaped_fmt(&mut self.html, text, false)?;
+            } else if let Some(child) = scraper::ElementRef::wrap(child) {
+                self.write_element(child)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_element(&mut self, elt: scraper::ElementRef) -> anyhow::Result<()> {
+        let name = elt.value().name();
+
+        if name == "p" {
+            let text = elt.text().collect::<String>();
+            if is_shortcode(&text) {
+                return self.write_shortcode(&text);
+            }
+        }
+
+        // Generated classes are replaced by their styles, except link colors and underlines that
+        // are on the span around links, and are skipped like for the doc JSON.
+        let wraps_link = elt.children().filter_map(scraper::ElementRef::wrap).any(|child| child.value().name() == "a");
+        let mut classes = Vec::new();
+        let mut style = elt.value().attr("style").unwrap_or_default().to_string();
+        for class in elt.value().classes() {
+            if is_export_class(class) {
+                if !wraps_link {
+                    style += self.class_styles.get(class).map_or("", |s| s.as_str());
+                }
+            } else {
+                classes.push(class.to_string());
+            }
+        }
+        match self.options.styling {
+            Some(styling) if styling.mode == StylingMode::Classes && !style.is_empty() => {
+                classes.push(self.styles.class_for(styling, &style));
+                style.clear();
+            },
+            _ => {},
+        }
+
+        self.html.push('<');
+        self.html.push_str(name);
+        // Sorted for a stable output
+        for (attr, value) in elt.value().attrs().sorted() {
+            let value = match attr {
+                "class" | "style" => continue,
+                "href" => match self.export_link(value) {
+                    Some(url) => url,
+                    None => continue,
+                },
+                _ => value.to_string(),
+            };
+            self.write_attr(attr, &value)?;
+        }
+        if !classes.is_empty() {
+            self.write_attr("class", &classes.join(" "))?;
+        }
+        if !style.is_empty() {
+            self.write_attr("style", &style)?;
+        }
+        self.html.push('>');
+
+        if !VOID_ELEMENTS.contains(&name) {
+            self.write_children(elt)?;
+            write!(self.html, "</{}>", name)?;
+        }
+        Ok(())
+    }
+
+    fn write_attr(&mut self, name: &str, value: &str) -> anyhow::Result<()> {
+        write!(self.html, " {}=\"", name)?;
+        crate::html::write_escaped_fmt(&mut self.html, value, true)?;
+        self.html.push('"');
+        Ok(())
+    }
+
+    /// Link URL without the export's Google redirect, or `None` if it's not allowed.
+    fn export_link(&mut self, href: &str) -> Option<String> {
+        let url = url::Url::parse(href).ok()
+            .filter(|url| url.host_str() == Some("www.google.com") && url.path() == "/url")
+            .and_then(|url| url.query_pairs().find(|(key, _)| key == "q").map(|(_, value)| value.into_owned()))
+            .unwrap_or_else(|| href.to_string());
+
+        if let Some(sanitize) = self.options.sanitize {
+            if !url.starts_with('#') && !sanitize::is_safe_url(sanitize, &url) {
+                self.warnings.push(format!("Removed link with forbidden URL '{}'", url));
+                return None;
+            }
+        }
+        Some(url)
+    }
+
+    /// Same as shortcode paragraphs of the doc JSON.
+    fn write_shortcode(&mut self, text: &str) -> anyhow::Result<()> {
+        if let Some((tag, _)) = AttrTag::parse(text.trim())? {
+            match tag {
+                AttrTag::Start(attrs) => write!(self.html, "<div{}>", attrs)?,
+                AttrTag::End => self.html.push_str("</div>"),
+            }
+            return Ok(());
+        }
+
+        let mut html = String::new();
+        HtmlRenderer::write_shortcode(text, &mut html)?;
+        match self.options.sanitize {
+            Some(sanitize) => self.html += &sanitize::clean_html(sanitize, &html, &mut self.warnings),
+            None => self.html += &html,
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1,19 +1,24 @@
 //! Representation of the Google Docs that constitute the website.
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 use std::path::{Path, PathBuf};
 
 use anyhow::Context;
 use rayon::prelude::*;
 use std::fs;
 use serde::{Deserialize, Deserializer};
-use chrono::{NaiveDateTime, Utc};
-use itertools::Itertools;
+use chrono::{DateTime, Duration, FixedOffset, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use crate::config::{ColumnType, Config};
+use crate::data_sheets::DATA_SLUG_PREFIX;
+use crate::layout;
+use serde::de::IgnoredAny;
+use serde_json::Value;
 use lazy_regex::regex;
 use lazy_static::lazy_static;
 use regex::Regex;
 
-pub fn download_toc(toc_url: &str, download_dir: &Path) -> anyhow::Result<Vec<DocData>> {
+pub fn download_toc(toc_url: &str, download_dir: &Path, config: &Config) -> anyhow::Result<Vec<DocData>> {
     fs::create_dir_all(download_dir).with_context(|| format!("Cannot create directory {:?}", download_dir))?;
 
     println!("Downloading ToC from {}", toc_url);
@@ -24,7 +29,7 @@ pub fn download_toc(toc_url: &str, download_dir: &Path) -> anyhow::Result<Vec<Do
     let toc_path = download_dir.join("pages.csv");
     fs::write(&toc_path, &csvtext).with_context(|| format!("Failed to write ToC spreadsheet {:?}", &toc_path))?;
 
-    let docs = DocData::read_csv(csvtext.as_bytes())?;
+    let docs = DocData::read_csv(csvtext.as_bytes(), config)?;
 
     Ok(docs)
 }
@@ -64,105 +69,551 @@ pub struct DocData {
     /// Target path. Has a leading '/' but no trailing '/'
     pub slug: String,
     pub author: Option<String>,
-    pub category: Option<String>,
+    /// Language code, for multilingual sites
+    pub lang: Option<String>,
+    /// Identifies the translations of a page
+    pub translation_key: Option<String>,
+    /// Comma or semicolon separated list
+    #[serde(rename = "category", alias = "categories", deserialize_with = "deser_list", default)]
+    pub categories: Vec<String>,
+    /// Comma or semicolon separated list
+    #[serde(alias = "tag", deserialize_with = "deser_list", default)]
+    pub tags: Vec<String>,
+    /// Custom taxonomies, from the columns listed in the config, as Hugo key -> terms
+    #[serde(skip, default)]
+    pub taxonomies: BTreeMap<String, Vec<String>>,
     pub weight: Option<i16>,
-    #[serde(deserialize_with = "deser_uppercase_bool")]
+    /// Content section, used to select a layout rule
+    pub section: Option<String>,
+    /// Summary of the page, used with the `column` summary strategy
+    pub summary: Option<String>,
+    /// Names of the Hugo menus the page is in, comma or semicolon separated
+    #[serde(rename = "menu", alias = "menus", deserialize_with = "deser_list", default)]
+    pub menus: Vec<String>,
+    /// Slug of the parent menu entry. Defaults to the closest ancestor page in the menu.
+    pub menu_parent: Option<String>,
+    /// Tabs exported by `/#data/` rows, comma or semicolon separated. `*` for all tabs.
+    #[serde(alias = "data_tab", deserialize_with = "deser_list", default)]
+    pub data_tabs: Vec<String>,
+    /// Old slugs that redirect to this page, comma or semicolon separated
+    #[serde(alias = "alias", deserialize_with = "deser_list", default)]
+    pub aliases: Vec<String>,
+    #[serde(skip, default)]
     pub publish: bool,
-    #[serde(deserialize_with = "deser_csv_date_option", default)]
+    #[serde(skip, default)]
     pub publish_date: Option<DateTimeWithDefault>,
-    #[serde(deserialize_with = "deser_csv_date_option", default)]
+    #[serde(skip, default)]
     pub update_date: Option<DateTimeWithDefault>,
+    /// Date after which the page is removed from the site
+    #[serde(skip, default)]
+    pub expiry_date: Option<DateTimeWithDefault>,
+    /// Dates as found in the ToC. They're parsed according to the config by `read_records`.
+    #[serde(rename = "publish_date", default)]
+    raw_publish_date: String,
+    #[serde(rename = "update_date", default)]
+    raw_update_date: String,
+    #[serde(rename = "expiry_date", default)]
+    raw_expiry_date: String,
+    /// Flags as found in the ToC. They're checked by `read_records`.
+    #[serde(rename = "publish")]
+    raw_publish: String,
+    #[serde(rename = "trusted", default)]
+    raw_trusted: String,
     /// "Publish to web" URL, used to get the HTML rendering of the doc.
     pub gdoc_pub_url: Option<String>,
     /// URL of the doc, used to translate links.
     pub gdoc_url: Option<String>,
+    /// Approved revision to publish instead of the live doc: a Drive revision id, or `published`
+    /// for the last revision published to the web. Revisions are rendered from their HTML export.
+    pub revision: Option<String>,
+    /// HTML body of pages generated from spreadsheet rows
+    pub body: Option<String>,
+    /// Markdown body of pages generated from spreadsheet rows
+    pub body_markdown: Option<String>,
+    /// Trusted docs can inject arbitrary HTML, which is sanitized otherwise.
+    #[serde(skip, default)]
+    pub trusted: bool,
     /// Relative path of the downloaded html
     #[serde(skip, default)]
     pub download_path: PathBuf,
-    /// Arbitrary data that will be forwarded to the frontmatter
+    /// Spreadsheet row number, if known
+    #[serde(skip, default)]
+    pub row: Option<u64>,
+    /// Origin of rows that don't come from a spreadsheet (e.g. a Drive file), used in problems
+    #[serde(skip, default)]
+    pub source: Option<String>,
+    /// Arbitrary data that will be forwarded to the frontmatter. Values are typed according to
+    /// `column_types` in the config, and dotted column names are nested objects.
     // #[serde(rename="type")]
     // pub page_type: Option<String>,
     // pub layout: Option<String>,
+    #[serde(skip, default)]
+    pub other: BTreeMap<String, serde_json::Value>,
+    /// Names of the columns that go in `other`. Values are read from the record, as the CSV
+    /// deserializer infers types and would turn `01.50` into `1.5`.
     #[serde(flatten)]
-    pub other: BTreeMap<String, String>
+    other_columns: BTreeMap<String, IgnoredAny>,
 }
 
 impl DocData {
-    pub fn read_csv(reader: impl std::io::Read) -> csv::Result<Vec<DocData>> {
+    pub fn read_csv(reader: impl std::io::Read, config: &Config) -> anyhow::Result<Vec<DocData>> {
         let mut rdr = csv::ReaderBuilder::new().from_reader(reader);
+        let headers = rdr.headers()?.clone();
 
-        rdr.deserialize()
+        let records = rdr.into_records()
             // First line after the header is the human-readable column names: skip it
             .skip(1)
-            .map_ok(|mut doc: DocData| {
+            .collect::<csv::Result<Vec<_>>>()?;
+
+        Self::read_records(&headers, &records, config)
+    }
 
-                // Cleanup gdoc URLs that may contain a fragment
-                if let Some(gdoc_url) = doc.gdoc_url.as_mut() {
-                    if let Some(frag_pos) = gdoc_url.find('#') {
-                        gdoc_url.truncate(frag_pos);
+    /// Read docs from all the rows of the ToC spreadsheet, including the header row.
+    pub fn read_sheet_rows(rows: &[csv::StringRecord], config: &Config) -> anyhow::Result<Vec<DocData>> {
+        match rows.split_first() {
+            // First line after the header is the human-readable column names: skip it
+            Some((headers, records)) => Self::read_records(headers, records.get(1..).unwrap_or_default(), config),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Read docs from spreadsheet rows, and validate them. All problems found are returned as a
+    /// `TocProblems` error.
+    pub fn read_records(headers: &csv::StringRecord, records: &[csv::StringRecord], config: &Config) -> anyhow::Result<Vec<DocData>> {
+        Self::read_records_with_sources(headers, records, &[], config)
+    }
+
+    /// Read docs from ToC rows, whatever their origin (spreadsheet, Drive folder), and validate
+    /// them. `sources` describe the origin of records that are not spreadsheet rows, and are used
+    /// in problems instead of row numbers.
+    pub fn read_records_with_sources(headers: &csv::StringRecord, records: &[csv::StringRecord], sources: &[String], config: &Config) -> anyhow::Result<Vec<DocData>> {
+        let mut docs = Vec::new();
+        let mut problems = Vec::new();
+
+        for (idx, record) in records.iter().enumerate() {
+            // Records are 0-based and include the header row
+            let row = record.position().map(|pos| pos.record() + 1);
+            let source = sources.get(idx).cloned();
+            match record.deserialize::<DocData>(Some(headers)) {
+                Ok(mut doc) => {
+                    doc.row = row;
+                    doc.source = source;
+                    if doc.lang.is_none() {
+                        doc.lang = config.languages.default.clone();
                     }
+                    problems.extend(doc.parse_flags());
+                    problems.extend(doc.parse_dates(config));
+                    problems.extend(doc.read_other_columns(headers, record, config));
+                    docs.push(Self::normalize(doc));
+                },
+                Err(err) => problems.push(TocProblem::from_csv_error(row, source, headers, &err)),
+            }
+        }
+
+        problems.extend(check_docs(&docs, config));
+
+        if problems.is_empty() {
+            Ok(docs)
+        } else {
+            Err(TocProblems(problems).into())
+        }
+    }
+
+    fn parse_flags(&mut self) -> Vec<TocProblem> {
+        let mut problems = Vec::new();
+
+        match self.raw_publish.as_str() {
+            "TRUE" => self.publish = true,
+            "FALSE" => self.publish = false,
+            value => problems.push(TocProblem::new(self, "publish", format!("Expecting TRUE or FALSE, got {}", value))),
+        }
+        match self.raw_trusted.as_str() {
+            "TRUE" => self.trusted = true,
+            "FALSE" | "" => self.trusted = false,
+            value => problems.push(TocProblem::new(self, "trusted", format!("Expecting TRUE, FALSE or nothing, got {}", value))),
+        }
+
+        problems
+    }
+
+    fn parse_dates(&mut self, config: &Config) -> Vec<TocProblem> {
+        let mut problems = Vec::new();
+        let mut parse = |column: &str, value: &str| {
+            if value.is_empty() {
+                return None;
+            }
+            match parse_date(value, config) {
+                Some(date) => Some(DateTimeWithDefault(date)),
+                None => {
+                    problems.push(TocProblem::new(self, column, format!(
+                        "invalid date '{}', expecting ISO 8601 or one of {:?}", value, config.date_formats
+                    )));
+                    None
                 }
+            }
+        };
+
+        let publish_date = parse("publish_date", &self.raw_publish_date);
+        let update_date = parse("update_date", &self.raw_update_date);
+        let expiry_date = parse("expiry_date", &self.raw_expiry_date);
+        self.publish_date = publish_date;
+        self.update_date = update_date;
+        self.expiry_date = expiry_date;
+
+        problems
+    }
 
-                // Normalize slugs (actually URL paths) so they have a leading '/' and no trailing '/'
-                if doc.slug.ends_with('/') {
-                    doc.slug.truncate(doc.slug.len() - 1)
+    /// Read custom taxonomies and the typed values of `other`.
+    fn read_other_columns(&mut self, headers: &csv::StringRecord, record: &csv::StringRecord, config: &Config) -> Vec<TocProblem> {
+        let mut problems = Vec::new();
+
+        for (idx, column) in headers.iter().enumerate() {
+            if !self.other_columns.contains_key(column) {
+                continue;
+            }
+            let value = record.get(idx).unwrap_or_default();
+            if value.is_empty() {
+                continue;
+            }
+
+            if let Some(key) = config.taxonomies.get(column) {
+                let terms = split_list(value);
+                if !terms.is_empty() {
+                    self.taxonomies.entry(key.clone()).or_default().extend(terms);
                 }
-                if !doc.slug.starts_with('/') {
-                    doc.slug.insert(0, '/');
+                continue;
+            }
+
+            let column_type = config.column_types.get(column).copied().unwrap_or_default();
+            let value = match convert_value(value, column_type, config) {
+                Ok(value) => value,
+                Err(message) => {
+                    problems.push(TocProblem::new(self, column, message));
+                    continue;
                 }
+            };
+
+            if let Err(message) = insert_dotted(&mut self.other, column, value) {
+                problems.push(TocProblem::new(self, column, message));
+            }
+        }
+
+        problems
+    }
+
+    /// Why the doc is not on the site at a given date, if it isn't.
+    pub fn unpublished_reason(&self, now: DateTime<FixedOffset>) -> Option<String> {
+        if !self.publish {
+            return Some("not published".to_string());
+        }
+        if let Some(DateTimeWithDefault(date)) = self.publish_date {
+            if date > now {
+                return Some(format!("scheduled for {}", date.to_rfc3339()));
+            }
+        }
+        if let Some(DateTimeWithDefault(date)) = self.expiry_date {
+            if date <= now {
+                return Some(format!("expired on {}", date.to_rfc3339()));
+            }
+        }
+        None
+    }
+
+    /// Is the doc on the site at a given date?
+    pub fn is_live(&self, now: DateTime<FixedOffset>) -> bool {
+        self.unpublished_reason(now).is_none()
+    }
+
+    fn normalize(mut doc: DocData) -> DocData {
+        // Cleanup gdoc URLs that may contain a fragment
+        if let Some(gdoc_url) = doc.gdoc_url.as_mut() {
+            if let Some(frag_pos) = gdoc_url.find('#') {
+                gdoc_url.truncate(frag_pos);
+            }
+        }
+
+        doc.slug = normalize_slug(&doc.slug);
+        doc.aliases = doc.aliases.iter().map(|alias| normalize_slug(alias)).collect();
+
+        // Compute the download path from the slug/url
+        let flat_slug = if doc.slug.len() == 1 {
+            "_index".to_string()
+        } else {
+            doc.slug[1..].replace('/', "_")
+        };
+        doc.download_path = format!("{}.html", flat_slug).into();
+
+        // Done
+        doc
+    }
+}
+
+/// Normalize slugs (actually URL paths) so they have a leading '/' and no trailing '/'
+pub fn normalize_slug(slug: &str) -> String {
+    let mut slug = slug.to_string();
+    if slug.ends_with('/') {
+        slug.truncate(slug.len() - 1)
+    }
+    if !slug.starts_with('/') {
+        slug.insert(0, '/');
+    }
+    slug
+}
+
+//----- ToC validation
+
+/// A problem found in a ToC row.
+#[derive(Debug)]
+pub struct TocProblem {
+    /// Spreadsheet row number, if known
+    pub row: Option<u64>,
+    /// Origin of the row if it's not a spreadsheet row
+    pub source: Option<String>,
+    pub column: Option<String>,
+    pub message: String,
+}
+
+impl TocProblem {
+    fn new(doc: &DocData, column: &str, message: String) -> TocProblem {
+        TocProblem {
+            row: doc.row,
+            source: doc.source.clone(),
+            column: Some(column.to_string()),
+            message,
+        }
+    }
+
+    fn from_csv_error(row: Option<u64>, source: Option<String>, headers: &csv::StringRecord, err: &csv::Error) -> TocProblem {
+        match err.kind() {
+            csv::ErrorKind::Deserialize { err, .. } => TocProblem {
+                row,
+                source,
+                column: err.field().and_then(|idx| headers.get(idx as usize)).map(|h| h.to_string()),
+                message: err.kind().to_string(),
+            },
+            _ => TocProblem { row, source, column: None, message: err.to_string() },
+        }
+    }
+}
 
-                // Compute the download path from the slug/url
-                let flat_slug = if doc.slug.len() == 1 {
-                    "_index".to_string()
+impl fmt::Display for TocProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.source, self.row) {
+            (Some(source), _) => write!(f, "{}", source)?,
+            (None, Some(row)) => write!(f, "row {}", row)?,
+            (None, None) => write!(f, "unknown row")?,
+        }
+        if let Some(column) = &self.column {
+            write!(f, ", column '{}'", column)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// All the problems found in the ToC.
+#[derive(Debug)]
+pub struct TocProblems(pub Vec<TocProblem>);
+
+impl fmt::Display for TocProblems {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} problem(s) in the table of contents", self.0.len())?;
+        for problem in &self.0 {
+            write!(f, "\n  - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for TocProblems {}
+
+/// Check the consistency of ToC rows: unique slugs, docs and translations, published docs have a
+/// GDoc URL, and aliases and content paths don't collide with other pages.
+pub fn check_docs(docs: &[DocData], config: &Config) -> Vec<TocProblem> {
+    let mut problems = Vec::new();
+
+    let row_of = |doc: &DocData| match (&doc.source, doc.row) {
+        (Some(source), _) => source.clone(),
+        (None, Some(row)) => format!("row {}", row),
+        (None, None) => "row ?".to_string(),
+    };
+
+    let mut slugs: HashMap<&str, &DocData> = HashMap::new();
+    let mut doc_ids: HashMap<&str, &DocData> = HashMap::new();
+    let mut translations: HashMap<(&str, Option<&str>), &DocData> = HashMap::new();
+
+    for doc in docs {
+        if let Some(other) = slugs.get(doc.slug.as_str()) {
+            problems.push(TocProblem::new(doc, "slug", format!(
+                "duplicate slug '{}', also on {}", doc.slug, row_of(other)
+            )));
+        } else {
+            slugs.insert(&doc.slug, doc);
+        }
+
+        match &doc.gdoc_url {
+            Some(url) => match get_doc_id(url) {
+                Some(id) => if let Some(other) = doc_ids.get(id) {
+                    problems.push(TocProblem::new(doc, "gdoc_url", format!(
+                        "document already published as '{}' on {}", other.slug, row_of(other)
+                    )));
                 } else {
-                    doc.slug[1..].replace('/', "_")
-                };
-                doc.download_path = format!("{}.html", flat_slug).into();
+                    doc_ids.insert(id, doc);
+                },
+                None => problems.push(TocProblem::new(doc, "gdoc_url", format!(
+                    "not a Google Docs URL: '{}'", url
+                ))),
+            },
+            None if doc.publish && doc.body.is_none() && doc.body_markdown.is_none() => {
+                problems.push(TocProblem::new(doc, "gdoc_url", "missing URL on a published page".to_string()));
+            },
+            None => {},
+        }
+
+        if let Some(key) = &doc.translation_key {
+            if let Some(other) = translations.get(&(key.as_str(), doc.lang.as_deref())) {
+                problems.push(TocProblem::new(doc, "translation_key", format!(
+                    "translation '{}' already exists for language '{}' on {}",
+                    key, doc.lang.as_deref().unwrap_or_default(), row_of(other)
+                )));
+            } else {
+                translations.insert((key, doc.lang.as_deref()), doc);
+            }
+        }
+    }
 
-                // Remove empty "other" entries
-                doc.other.retain(|_, v| v.len() > 0);
+    for doc in docs {
+        for alias in &doc.aliases {
+            if let Some(other) = slugs.get(alias.as_str()) {
+                problems.push(TocProblem::new(doc, "aliases", format!(
+                    "alias '{}' is the slug of the page on {}", alias, row_of(other)
+                )));
+            }
+        }
+    }
 
-                // Done
-                doc
-            })
-            .collect()
+    // Pages moved by layout rules must not collide with other pages. Check pages written at their
+    // slug first, so that problems are reported on moved pages.
+    let mut located = docs.iter()
+        .filter(|doc| !doc.slug.starts_with(DATA_SLUG_PREFIX))
+        .map(|doc| (doc, layout::locate(doc, &config.layout).path))
+        .collect::<Vec<_>>();
+    located.sort_by_key(|(doc, path)| *path != doc.slug);
+
+    let mut paths: HashMap<&str, &DocData> = HashMap::new();
+    for (doc, path) in &located {
+        match paths.get(path.as_str()) {
+            // Same slug: already reported
+            Some(other) if other.slug != doc.slug => {
+                problems.push(TocProblem::new(doc, "slug", format!(
+                    "content path '{}' collides with page on {}", path, row_of(other)
+                )));
+            },
+            Some(_) => {},
+            None => {
+                paths.insert(path, doc);
+            },
+        }
     }
+
+    problems.sort_by_key(|problem| problem.row);
+    problems
 }
 
-fn deser_uppercase_bool<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
-    let s = String::deserialize(deserializer)?;
-    match s.as_str() {
-        "TRUE" => Ok(true),
-        "FALSE" => Ok(false),
-        _ => Err(serde::de::Error::custom(format!("Expecting TRUE or FALSE, got {}", s))),
+/// Convert a ToC value to its declared type.
+pub(crate) fn convert_value(value: &str, column_type: ColumnType, config: &Config) -> Result<Value, String> {
+    match column_type {
+        ColumnType::String => Ok(Value::String(value.to_string())),
+        ColumnType::Bool => match value.to_ascii_uppercase().as_str() {
+            "TRUE" => Ok(Value::Bool(true)),
+            "FALSE" => Ok(Value::Bool(false)),
+            _ => Err(format!("Expecting TRUE or FALSE, got {}", value)),
+        },
+        ColumnType::Int => value.trim().parse::<i64>()
+            .map(Value::from)
+            .map_err(|_| format!("Expecting an integer, got {}", value)),
+        ColumnType::Float => value.trim().parse::<f64>().ok()
+            .and_then(serde_json::Number::from_f64)
+            .map(Value::Number)
+            .ok_or_else(|| format!("Expecting a number, got {}", value)),
+        ColumnType::Date => parse_date(value, config)
+            .map(|date| Value::String(date.to_rfc3339()))
+            .ok_or_else(|| format!("invalid date '{}', expecting ISO 8601 or one of {:?}", value, config.date_formats)),
+        ColumnType::List => Ok(Value::from(split_list(value))),
+        ColumnType::Json => serde_json::from_str(value)
+            .map_err(|e| format!("Invalid JSON: {}", e)),
     }
 }
 
+/// Insert a value in a map, dotted names (`params.hero.color`) being nested objects.
+pub(crate) fn insert_dotted(map: &mut BTreeMap<String, Value>, name: &str, value: Value) -> Result<(), String> {
+    match name.split_once('.') {
+        None => {
+            if map.contains_key(name) {
+                return Err(format!("'{}' is defined by several columns", name));
+            }
+            map.insert(name.to_string(), value);
+        },
+        Some((first, rest)) => {
+            let entry = map.entry(first.to_string())
+                .or_insert_with(|| Value::Object(serde_json::Map::new()));
+            let Value::Object(object) = entry else {
+                return Err(format!("'{}' is both a value and an object", first));
+            };
+            // serde_json::Map is a BTreeMap unless the "preserve_order" feature is enabled
+            let mut nested = std::mem::take(object).into_iter().collect::<BTreeMap<_, _>>();
+            let result = insert_dotted(&mut nested, rest, value);
+            *object = nested.into_iter().collect();
+            result?;
+        },
+    }
+    Ok(())
+}
+
+/// Split a comma or semicolon separated list, ignoring empty items.
+pub fn split_list(s: &str) -> Vec<String> {
+    s.split([',', ';'])
+        .map(|item| item.trim())
+        .filter(|item| !item.is_empty())
+        .map(|item| item.to_string())
+        .collect()
+}
+
+fn deser_list<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    Ok(split_list(&s))
+}
+
 #[derive(Debug, Serialize, Deserialize)]
-pub struct DateTimeWithDefault(pub chrono::DateTime<Utc>);
+pub struct DateTimeWithDefault(pub DateTime<FixedOffset>);
 
 impl Default for DateTimeWithDefault {
     fn default() -> Self {
-        DateTimeWithDefault(chrono::DateTime::<Utc>::MIN_UTC)
+        DateTimeWithDefault(DateTime::<Utc>::MIN_UTC.fixed_offset())
     }
 }
 
-fn deser_csv_date_option<'de, D: Deserializer<'de>>(
-    deserializer: D,
-) -> Result<Option<DateTimeWithDefault>, D::Error> {
-    static FORMAT: &str = "%d/%m/%Y %H:%M:%S";
+/// Parse a ToC date. Dates with an offset (e.g. typed dates read with the Sheets API) are
+/// converted to the site timezone, and other dates are interpreted in that timezone.
+pub fn parse_date(s: &str, config: &Config) -> Option<DateTime<FixedOffset>> {
+    static ISO_FORMATS: &[&str] = &["%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M", "%Y-%m-%d"];
 
-    let s = String::deserialize(deserializer)?;
-    if s.is_empty() {
-        Ok(None)
-    } else {
-        let t = NaiveDateTime::parse_from_str(&s, FORMAT)
-            .with_context(|| format!("Failed to parse date '{}' with format {}", s, FORMAT))
-            .map_err(serde::de::Error::custom)?
-            .and_local_timezone(Utc)
-            .unwrap();
-        Ok(Some(DateTimeWithDefault(t)))
+    let s = s.trim();
+    if let Ok(date) = DateTime::parse_from_rfc3339(s) {
+        return Some(date.with_timezone(&config.timezone).fixed_offset());
     }
+
+    let naive = ISO_FORMATS.iter()
+        .copied()
+        .chain(config.date_formats.iter().map(|f| f.as_str()))
+        .find_map(|format| {
+            NaiveDateTime::parse_from_str(s, format).ok()
+                .or_else(|| NaiveDate::parse_from_str(s, format).ok().and_then(|d| d.and_hms_opt(0, 0, 0)))
+        })?;
+
+    // Non-existing local times (DST gaps) are moved forward
+    config.timezone.from_local_datetime(&naive).earliest()
+        .or_else(|| config.timezone.from_local_datetime(&(naive + Duration::hours(1))).earliest())
+        .map(|date| date.fixed_offset())
 }
 
 lazy_static! {
@@ -177,8 +628,61 @@ pub fn get_doc_id(url: &str) -> Option<&str> {
         .map(|m| m.as_str())
 }
 
+lazy_static! {
+    static ref DRIVE_FILE_ID_RE: Regex = Regex::new(concat!(
+        "^https://(docs.google.com/(spreadsheets|presentation|drawings)(/u/[0-9]+)?/d/",
+        "|drive.google.com/(file(/u/[0-9]+)?/d/|(open|uc)\\?(.*&)?id=))",
+        "(?P<id>[A-Za-z0-9_-]+)"
+    )).unwrap();
+}
+
+lazy_static! {
+    static ref FOLDER_ID_RE: Regex =
+        Regex::new("^https://drive.google.com/drive(/u/[0-9]+)?/folders/([A-Za-z0-9_-]+)").unwrap();
+}
+
+/// Extracts the folder id from a Drive folder URL. Anything else is considered to be an id.
+pub fn get_folder_id(url: &str) -> &str {
+    FOLDER_ID_RE.captures(url)
+        .and_then(|captures| captures.get(2))
+        .map_or(url, |m| m.as_str())
+}
+
+/// Converts a name to a URL path segment: lowercase ascii letters, digits, '-' and '_'.
+/// Accented letters are converted to their base letter.
+pub fn slugify(name: &str) -> String {
+    let mut slug = String::new();
+    for c in name.to_lowercase().chars() {
+        let c = match c {
+            'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' => 'a',
+            'ç' => 'c',
+            'è' | 'é' | 'ê' | 'ë' => 'e',
+            'ì' | 'í' | 'î' | 'ï' => 'i',
+            'ñ' => 'n',
+            'ò' | 'ó' | 'ô' | 'õ' | 'ö' => 'o',
+            'ù' | 'ú' | 'û' | 'ü' => 'u',
+            'ý' | 'ÿ' => 'y',
+            c if c.is_ascii_alphanumeric() || c == '_' => c,
+            _ => '-',
+        };
+        if c != '-' || !(slug.is_empty() || slug.ends_with('-')) {
+            slug.push(c);
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
+
+/// Extracts the file id, if any, from the URL of a Drive file or of a Google Sheets, Slides or
+/// Drawings file. Google Docs documents are not considered, as they're site pages.
+pub fn get_drive_file_id(url: &str) -> Option<&str> {
+    DRIVE_FILE_ID_RE.captures(url)
+        .and_then(|captures| captures.name("id"))
+        .map(|m| m.as_str())
+}
+
 #[cfg(test)]
 mod test {
+    use itertools::Itertools;
 
     #[test]
     fn test_doc_id_extraction() {
@@ -193,4 +697,152 @@ mod test {
             super::get_doc_id("https://docs.google.com/document/u/0/d/rUSGSdveGGDaGxuPmDyXus/edit")
         );
     }
+
+    #[test]
+    fn test_drive_file_id_extraction() {
+
+        assert_eq!(
+            Some("1ZDNb8kcWRZf8Arw6D3NKksD"),
+            super::get_drive_file_id("https://docs.google.com/presentation/d/1ZDNb8kcWRZf8Arw6D3NKksD/edit#slide=id.p")
+        );
+
+        assert_eq!(
+            Some("1NvDFyAcVY-0HClOKd6IrKw6f"),
+            super::get_drive_file_id("https://drive.google.com/file/d/1NvDFyAcVY-0HClOKd6IrKw6f/view?usp=sharing")
+        );
+
+        assert_eq!(
+            Some("1NvDFyAcVY-0HClOKd6IrKw6f"),
+            super::get_drive_file_id("https://drive.google.com/open?id=1NvDFyAcVY-0HClOKd6IrKw6f")
+        );
+
+        assert_eq!(
+            None,
+            super::get_drive_file_id("https://docs.google.com/document/d/rUSGSdveGGDaGxuPmDyXus/edit")
+        );
+    }
+
+    #[test]
+    fn test_toc_problems() {
+        let csv = indoc::indoc! {"
+            title,slug,category,publish,trusted,gdoc_url
+            Title,Slug,Category,Publish,Trusted,GDoc
+            Home,/,,TRUE,maybe,https://docs.google.com/document/d/abc/edit
+            About,/about,,yes,,https://docs.google.com/document/d/def/edit
+            News,/news,News,TRUE,,https://docs.google.com/document/d/abc/edit
+            News again,/news/,,FALSE,,
+            Post,/posts/news,,TRUE,,
+        "};
+
+        let err = super::DocData::read_csv(csv.as_bytes(), &test_config()).unwrap_err();
+        let problems = &err.downcast_ref::<super::TocProblems>().unwrap().0;
+        let problems = problems.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(vec![
+            "row 3, column 'trusted': Expecting TRUE, FALSE or nothing, got maybe",
+            "row 4, column 'publish': Expecting TRUE or FALSE, got yes",
+            "row 5, column 'gdoc_url': document already published as '/' on row 3",
+            "row 5, column 'slug': content path '/posts/news' collides with page on row 7",
+            "row 6, column 'slug': duplicate slug '/news', also on row 5",
+            "row 7, column 'gdoc_url': missing URL on a published page",
+        ], problems);
+    }
+
+    fn test_config() -> crate::config::Config {
+        serde_yaml::from_str(indoc::indoc! {"
+            hugo_site_dir: site
+            timezone: Europe/Paris
+            date_formats: ['%m/%d/%Y %H:%M:%S', '%m/%d/%Y']
+        "}).unwrap()
+    }
+
+    #[test]
+    fn test_parse_date() {
+        let config = test_config();
+        let parse = |s| super::parse_date(s, &config).map(|d| d.to_rfc3339());
+
+        assert_eq!(Some("2024-03-01T00:00:00+01:00".to_string()), parse("2024-03-01"));
+        assert_eq!(Some("2024-07-01T10:30:00+02:00".to_string()), parse("2024-07-01T10:30:00"));
+        assert_eq!(Some("2024-07-01T14:00:00+02:00".to_string()), parse("2024-07-01T12:00:00Z"));
+        assert_eq!(Some("2024-03-12T09:00:00+01:00".to_string()), parse("03/12/2024 09:00:00"));
+        assert_eq!(Some("2024-03-12T00:00:00+01:00".to_string()), parse("03/12/2024"));
+        assert_eq!(None, parse("12 mars 2024"));
+    }
+
+    #[test]
+    fn test_taxonomies() {
+        let csv = indoc::indoc! {"
+            title,slug,categories,tags,publish,gdoc_url,themes
+            Title,Slug,Categories,Tags,Publish,GDoc,Themes
+            News,/news,News; Events,\"climate, energy\",TRUE,https://docs.google.com/document/d/abc/edit,\"water,\"
+        "};
+        let mut config = test_config();
+        config.taxonomies.insert("themes".to_string(), "topics".to_string());
+
+        let docs = super::DocData::read_csv(csv.as_bytes(), &config).unwrap();
+        assert_eq!(vec!["News", "Events"], docs[0].categories);
+        assert_eq!(vec!["climate", "energy"], docs[0].tags);
+        assert_eq!(Some(&vec!["water".to_string()]), docs[0].taxonomies.get("topics"));
+        assert!(docs[0].other.is_empty());
+    }
+
+    #[test]
+    fn test_typed_columns() {
+        let csv = indoc::indoc! {"
+            title,slug,publish,gdoc_url,featured,price,params.hero.color,params.hero.size,code,extra
+            Title,Slug,Publish,GDoc,Featured,Price,Hero color,Hero size,Code,Extra
+            News,/news,TRUE,https://docs.google.com/document/d/abc/edit,TRUE,01.50,red,3,0042,\"{\"\"a\"\": [1]}\"
+            Events,/events,TRUE,https://docs.google.com/document/d/def/edit,yes,,,big,,
+        "};
+        let mut config = test_config();
+        config.column_types = serde_yaml::from_str(indoc::indoc! {"
+            featured: bool
+            price: float
+            params.hero.size: int
+            extra: json
+        "}).unwrap();
+
+        let err = super::DocData::read_csv(csv.as_bytes(), &config).unwrap_err();
+        let problems = &err.downcast_ref::<super::TocProblems>().unwrap().0;
+        let problems = problems.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        assert_eq!(vec![
+            "row 4, column 'featured': Expecting TRUE or FALSE, got yes",
+            "row 4, column 'params.hero.size': Expecting an integer, got big",
+        ], problems);
+
+        let csv = csv.lines().take(3).join("\n");
+        let docs = super::DocData::read_csv(csv.as_bytes(), &config).unwrap();
+        assert_eq!(
+            serde_json::json!({
+                "featured": true,
+                "price": 1.5,
+                "params": { "hero": { "color": "red", "size": 3 } },
+                "code": "0042",
+                "extra": { "a": [1] },
+            }),
+            serde_json::to_value(&docs[0].other).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_scheduled_and_expired() {
+        let csv = indoc::indoc! {"
+            title,slug,publish,publish_date,expiry_date,gdoc_url
+            Title,Slug,Publish,Date,Expiry,GDoc
+            Event,/event,TRUE,2024-03-01,2024-04-01,https://docs.google.com/document/d/abc/edit
+        "};
+        let config = test_config();
+        let docs = super::DocData::read_csv(csv.as_bytes(), &config).unwrap();
+        let at = |date| super::parse_date(date, &config).unwrap();
+
+        assert_eq!(Some("scheduled for 2024-03-01T00:00:00+01:00".to_string()), docs[0].unpublished_reason(at("2024-02-29")));
+        assert!(docs[0].is_live(at("2024-03-01")));
+        assert_eq!(Some("expired on 2024-04-01T00:00:00+02:00".to_string()), docs[0].unpublished_reason(at("2024-04-01")));
+    }
+
+    #[test]
+    fn test_slugify() {
+        assert_eq!("page-d-accueil", super::slugify("Page d'accueil"));
+        assert_eq!("eco-anxiete_2023", super::slugify("  Éco-anxiété_2023 !"));
+    }
 }
@@ -1,24 +1,147 @@
 use std::collections::BTreeMap;
+use anyhow::Result;
 use crate::DateTimeWithDefault;
+use crate::config::{FrontMatterConfig, FrontMatterFormat};
+use crate::menus::MenuEntry;
+use crate::site_tree::PageLink;
 
 #[derive(Debug, Serialize, Default)]
 pub struct FrontMatter {
     pub markup: &'static str,
     pub author: Option<String>,
+    /// People who edited the doc
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub contributors: Vec<String>,
     pub title: String,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub draft: bool,
     pub date: Option<DateTimeWithDefault>,
     pub lastmod: Option<DateTimeWithDefault>,
+    #[serde(rename = "expiryDate", skip_serializing_if = "Option::is_none")]
+    pub expiry_date: Option<DateTimeWithDefault>,
     pub banner: Option<String>,
     pub slug: String,
     pub url: Option<String>,
+    /// Old URLs redirecting to this page
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    /// Language of the page, which is given by the file name or directory
+    #[serde(skip)]
+    pub lang: Option<String>,
+    /// Links the translations of a page
+    #[serde(rename = "translationKey", skip_serializing_if = "Option::is_none")]
+    pub translation_key: Option<String>,
     pub categories: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Custom taxonomies
+    #[serde(flatten)]
+    pub taxonomies: BTreeMap<String, Vec<String>>,
     // "weight" should be "categories_weight" but it doesn't seem to work as advertised in Hugo's docs.
+    // It can be renamed with the `front_matter.keys` config if needed.
     pub weight: Option<i16>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub menus: BTreeMap<String, MenuEntry>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub breadcrumbs: Vec<PageLink>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prev: Option<PageLink>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next: Option<PageLink>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<PageLink>,
     pub summary: Option<String>,
     pub description: Option<String>, // same as summary, Hugo uses summary for page lists, and description for SEO
     pub inline_style: Option<String>,
     // not used in the publication process, but useful to distinguish generated pages
     pub gdoc_url: Option<String>,
     #[serde(flatten)]
-    pub other: BTreeMap<String, String>
+    pub other: BTreeMap<String, serde_json::Value>
+}
+
+impl FrontMatter {
+    /// Serialize the front matter with its delimiters, renaming or dropping keys according to
+    /// the config.
+    pub fn render(&self, config: &FrontMatterConfig) -> Result<String> {
+        let serde_yaml::Value::Mapping(values) = serde_yaml::to_value(self)? else {
+            unreachable!("front matter is a struct");
+        };
+
+        // A YAML mapping keeps the order of struct fields
+        let mut mapping = serde_yaml::Mapping::new();
+        for (key, value) in values {
+            let new_key = match key.as_str().and_then(|k| config.keys.get(k)) {
+                Some(Some(new_key)) if !new_key.is_empty() => new_key.as_str().into(),
+                Some(_) => continue,
+                None => key,
+            };
+            mapping.insert(new_key, value);
+        }
+        let value = serde_yaml::Value::Mapping(mapping);
+
+        Ok(match config.format {
+            FrontMatterFormat::Yaml => format!("---\n{}\n---\n", serde_yaml::to_string(&value)?),
+            FrontMatterFormat::Toml => {
+                // TOML has no null values
+                let value = toml::Value::try_from(without_nulls(value))?;
+                format!("+++\n{}\n+++\n", toml::to_string(&value)?)
+            },
+            FrontMatterFormat::Json => format!("{}\n", serde_json::to_string_pretty(&value)?),
+        })
+    }
+}
+
+fn without_nulls(value: serde_yaml::Value) -> serde_yaml::Value {
+    match value {
+        serde_yaml::Value::Mapping(mapping) => serde_yaml::Value::Mapping(mapping.into_iter()
+            .filter(|(_, v)| !v.is_null())
+            .map(|(k, v)| (k, without_nulls(v)))
+            .collect()),
+        serde_yaml::Value::Sequence(seq) => serde_yaml::Value::Sequence(seq.into_iter()
+            .filter(|v| !v.is_null())
+            .map(without_nulls)
+            .collect()),
+        value => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::FrontMatterConfig;
+    use super::FrontMatter;
+
+    #[test]
+    fn test_render() {
+        let fm = FrontMatter {
+            markup: "html",
+            title: "Tea".to_string(),
+            banner: Some("tea.jpg".to_string()),
+            slug: "_tea".to_string(),
+            gdoc_url: Some("https://docs.google.com/document/d/abc/edit".to_string()),
+            ..FrontMatter::default()
+        };
+
+        let config: FrontMatterConfig = serde_yaml::from_str(indoc::indoc! {"
+            format: toml
+            keys:
+              banner: images
+              gdoc_url: null
+              summary: ''
+        "}).unwrap();
+
+        assert_eq!(indoc::indoc! {r#"
+            +++
+            categories = []
+            images = "tea.jpg"
+            markup = "html"
+            slug = "_tea"
+            title = "Tea"
+
+            +++
+        "#}, fm.render(&config).unwrap());
+
+        let yaml = fm.render(&FrontMatterConfig::default()).unwrap();
+        assert!(yaml.starts_with("---\nmarkup: html\nauthor: null\ntitle: Tea\n"));
+        assert!(yaml.ends_with("gdoc_url: https://docs.google.com/document/d/abc/edit\n\n---\n"));
+    }
 }
@@ -0,0 +1,104 @@
+//! Location of pages in the Hugo content directory, given by the `layout` rules of the config.
+//!
+//! The first rule matching a doc gives its content directory and bundle type. Docs that match no
+//! rule are written at their slug, as a branch bundle if they have child pages and a leaf bundle
+//! otherwise.
+
+use crate::config::{Bundle, LayoutRule};
+use crate::gdocs_site::DocData;
+
+/// Where a page is written, relative to the content directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    /// Content path. Like slugs, it has a leading '/' and no trailing '/'. The file name is added
+    /// according to the bundle type.
+    pub path: String,
+    pub bundle: Bundle,
+}
+
+pub fn locate(doc: &DocData, rules: &[LayoutRule]) -> Location {
+    let Some(rule) = rules.iter().find(|rule| matches(rule, doc)) else {
+        return Location { path: doc.slug.clone(), bundle: Bundle::Auto };
+    };
+
+    let path = match &rule.dir {
+        Some(dir) => {
+            let slug = doc.slug.trim_end_matches('/');
+            let dir = format!("/{}", dir.trim_matches('/'));
+            // The slug prefix is replaced by the directory
+            let rel_slug = rule.slug_prefix.as_deref()
+                .and_then(|prefix| slug.strip_prefix(prefix.trim_end_matches('/')))
+                .unwrap_or(slug);
+            format!("{}{}", dir, rel_slug).replace("//", "/")
+        },
+        None => doc.slug.clone(),
+    };
+
+    Location { path, bundle: rule.bundle }
+}
+
+fn matches(rule: &LayoutRule, doc: &DocData) -> bool {
+    let category = rule.category.as_deref().is_none_or(|category| match category {
+        "*" => !doc.categories.is_empty(),
+        _ => doc.categories.iter().any(|c| c.eq_ignore_ascii_case(category)),
+    });
+    let section = rule.section.as_deref().is_none_or(|section| doc.section.as_deref() == Some(section));
+    let slug_prefix = rule.slug_prefix.as_deref().is_none_or(|prefix| {
+        let prefix = prefix.trim_end_matches('/');
+        doc.slug == prefix || doc.slug.starts_with(&format!("{}/", prefix))
+    });
+
+    category && section && slug_prefix
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{Bundle, Config};
+    use super::Location;
+
+    #[test]
+    fn test_layout_rules() {
+        let csv = indoc::indoc! {"
+            title,slug,category,section,publish
+            Title,Slug,Category,Section,Publish
+            Launch,/launch,News,,FALSE
+            Recipe,/tea,Recipes,,FALSE
+            Install,/documentation/install,,,FALSE
+            Guide,/guide,,docs,FALSE
+            About,/about,,,FALSE
+        "};
+
+        let config: Config = serde_yaml::from_str(indoc::indoc! {"
+            hugo_site_dir: site
+            layout:
+              - category: news
+                dir: news
+                bundle: file
+              - slug_prefix: /documentation
+                dir: docs
+              - section: docs
+                dir: docs
+                bundle: branch
+              - category: '*'
+                dir: posts
+                bundle: file
+        "}).unwrap();
+        let docs = crate::gdocs_site::DocData::read_csv(csv.as_bytes(), &config).unwrap();
+
+        let location = |path: &str, bundle| Location { path: path.to_string(), bundle };
+        let locations = docs.iter().map(|doc| super::locate(doc, &config.layout)).collect::<Vec<_>>();
+
+        assert_eq!(vec![
+            location("/news/launch", Bundle::File),
+            location("/posts/tea", Bundle::File),
+            location("/docs/install", Bundle::Auto),
+            location("/docs/guide", Bundle::Branch),
+            location("/about", Bundle::Auto),
+        ], locations);
+
+        // Default rules: categorized pages are posts
+        let config: Config = serde_yaml::from_str("hugo_site_dir: site").unwrap();
+        assert_eq!(location("/posts/launch", Bundle::File), super::locate(&docs[0], &config.layout));
+        assert_eq!(location("/about", Bundle::Auto), super::locate(&docs[4], &config.layout));
+    }
+}
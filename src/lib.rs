@@ -2,29 +2,77 @@
 extern crate serde_derive;
 
 use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
 
-use anyhow::{anyhow, bail};
+use anyhow::anyhow;
 use lazy_static::lazy_static;
 use gdocs_site::DateTimeWithDefault;
 
 pub mod config;
+pub mod data_sheets;
+pub mod drive_metadata;
+pub mod drive_toc;
 pub mod gdocs_site;
 mod images;
 mod hugo_site;
+pub mod layout;
+pub mod menus;
 pub mod gdoc_to_html;
 pub mod from_web_pub;
 pub mod experiments;
 pub mod publish;
 pub mod html;
+pub mod sanitize;
+pub mod sheet_pages;
+pub mod sheets;
+pub mod site_tree;
+pub mod slug_history;
+pub mod styles;
+pub mod suggestions;
 mod tweaks;
 
 use regex::Regex;
 use crate::gdocs_site::DocData;
+use crate::site_tree::SiteTree;
 
 #[derive(Default)]
 pub struct SiteData {
     url_to_slug: HashMap<String, String>,
     id_to_slug: HashMap<String, String>,
+    /// Translation key of docs, by doc id
+    id_to_translation_key: HashMap<String, String>,
+    /// Slugs of translated docs, by translation key and language
+    translations: HashMap<(String, String), String>,
+    /// Page hierarchy
+    pub tree: SiteTree,
+}
+
+/// A link to a GDoc that is not in the table of contents.
+#[derive(Debug, Clone)]
+pub struct UnknownDocLink {
+    pub doc_id: Option<String>,
+    pub url: String,
+}
+
+impl Display for UnknownDocLink {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(id) = &self.doc_id {
+            write!(f, "Found a link to a GDoc that is not in the page list: id={}", id)
+        } else {
+            write!(f, "Google doc url not found in site pages {}", self.url)
+        }
+    }
+}
+
+impl std::error::Error for UnknownDocLink {}
+
+/// A link to a GDoc that is not in the table of contents, and where it was found.
+#[derive(Debug)]
+pub struct BrokenLink {
+    /// Slug of the page containing the link
+    pub page: String,
+    pub text: String,
+    pub target: UnknownDocLink,
 }
 
 lazy_static! {
@@ -36,6 +84,8 @@ impl SiteData {
     pub fn new(docs: &Vec<DocData>) -> anyhow::Result<Self> {
         let mut url_to_slug = HashMap::new();
         let mut id_to_slug = HashMap::new();
+        let mut id_to_translation_key = HashMap::new();
+        let mut translations = HashMap::new();
         for doc in docs {
             if let Some(url) = doc.gdoc_pub_url.as_ref() {
                 url_to_slug.insert(url.clone(), doc.slug.clone());
@@ -44,12 +94,22 @@ impl SiteData {
                 let id = gdocs_site::get_doc_id(url)
                     .ok_or_else(|| anyhow!("Cannot extract doc id from {}", url))?;
                 id_to_slug.insert(id.to_string(), doc.slug.clone());
+                if let Some(key) = &doc.translation_key {
+                    id_to_translation_key.insert(id.to_string(), key.clone());
+                }
+            }
+            if let (Some(key), Some(lang)) = (&doc.translation_key, &doc.lang) {
+                translations.insert((key.clone(), lang.clone()), doc.slug.clone());
             }
         }
 
         Ok(SiteData {
             url_to_slug,
             id_to_slug,
+            id_to_translation_key,
+            translations,
+            // Built by the caller, which knows the layout rules and which pages are live
+            tree: SiteTree::default(),
         })
     }
 
@@ -57,9 +117,12 @@ impl SiteData {
     /// Rewrite a href URL to translate references to GDocs to internal site URLs.
     /// The URL fragment, if any, is kept.
     ///
-    pub fn rewrite_href(&self, href: &str) -> anyhow::Result<Option<String>> {
+    /// If `lang` is the language of the page containing the link, links to a GDoc that has a
+    /// translation in that language lead to the translation.
+    ///
+    pub fn rewrite_href(&self, href: &str, lang: Option<&str>) -> anyhow::Result<Option<String>> {
         if let Some((url, frag)) = href.split_once('#') {
-            if let Some(mut new_url) = self.rewrite_url(url)? {
+            if let Some(mut new_url) = self.rewrite_url(url, lang)? {
                 new_url.push('#');
                 new_url.push_str(frag);
                 Ok(Some(new_url))
@@ -67,17 +130,17 @@ impl SiteData {
                 Ok(None)
             }
         } else {
-            self.rewrite_url(href)
+            self.rewrite_url(href, lang)
         }
     }
 
-    fn rewrite_url(&self, url: &str) -> anyhow::Result<Option<String>> {
+    fn rewrite_url(&self, url: &str, lang: Option<&str>) -> anyhow::Result<Option<String>> {
         // It may happen that some links go through a redirect warning page.
         if url.starts_with("https://www.google.com/url?") {
             let url = reqwest::Url::parse(url).unwrap();
             if let Some((_, v)) = url.query_pairs().find(|(k, _)| k == "q") {
                 let url_param = v.trim();
-                return Ok(self.rewrite_url(url_param)?.or_else(|| Some(url_param.to_string())));
+                return Ok(self.rewrite_url(url_param, lang)?.or_else(|| Some(url_param.to_string())));
             }
         }
 
@@ -89,12 +152,11 @@ impl SiteData {
         // Reference to GDocs (internal site links)
         if url.starts_with("https://docs.google.com/document/") {
             return if let Some(id) = gdocs_site::get_doc_id(url) {
-                if let Some(slug) = self.id_to_slug.get(id) {
+                if let Some(slug) = self.translated_slug(id, lang).or_else(|| self.id_to_slug.get(id)) {
                     // Add a trailing slash
                     Ok(Some(format!("{}/", slug)))
                 } else {
-                    //Ok(None)
-                    bail!("Found a link to a GDoc that is not in the page list: id={}", id)
+                    Err(UnknownDocLink { doc_id: Some(id.to_string()), url: url.to_string() }.into())
                 }
             } else {
                 // Legacy lookup, for "export to web" URLs
@@ -104,8 +166,7 @@ impl SiteData {
                 if let Some(slug) = self.url_to_slug.get(url.as_ref()) {
                     Ok(Some(format!("{}/", slug)))
                 } else {
-                    //Ok(None)
-                    bail!("Google doc url not found in site pages {}", url)
+                    Err(UnknownDocLink { doc_id: None, url: url.to_string() }.into())
                 }
             }
         }
@@ -113,4 +174,38 @@ impl SiteData {
         // No rewrite
         Ok(None)
     }
+
+    fn translated_slug(&self, doc_id: &str, lang: Option<&str>) -> Option<&String> {
+        let key = self.id_to_translation_key.get(doc_id)?;
+        self.translations.get(&(key.clone(), lang?.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+    use crate::gdocs_site::DocData;
+    use crate::SiteData;
+
+    #[test]
+    fn test_translated_links() {
+        let csv = indoc::indoc! {"
+            title,slug,lang,translation_key,publish,gdoc_url
+            Title,Slug,Lang,Translation,Publish,GDoc
+            About,/about,,about,TRUE,https://docs.google.com/document/d/abc/edit
+            A propos,/fr/a-propos,fr,about,TRUE,https://docs.google.com/document/d/def/edit
+            Contact,/contact,,,TRUE,https://docs.google.com/document/d/ghi/edit
+        "};
+        let config: Config = serde_yaml::from_str("{hugo_site_dir: site, languages: {default: en}}").unwrap();
+        let docs = DocData::read_csv(csv.as_bytes(), &config).unwrap();
+        assert_eq!(Some("en"), docs[0].lang.as_deref());
+
+        let site_data = SiteData::new(&docs).unwrap();
+        let about = "https://docs.google.com/document/d/abc/edit#heading=h.1";
+        let contact = "https://docs.google.com/document/d/ghi/edit";
+
+        assert_eq!(Some("/fr/a-propos/#heading=h.1".to_string()), site_data.rewrite_href(about, Some("fr")).unwrap());
+        assert_eq!(Some("/about/#heading=h.1".to_string()), site_data.rewrite_href(about, Some("en")).unwrap());
+        assert_eq!(Some("/contact/".to_string()), site_data.rewrite_href(contact, Some("fr")).unwrap());
+    }
 }
@@ -42,8 +42,12 @@ fn main0(args: RootCommand, config: Config) -> anyhow::Result<()> {
             rt.block_on(experiments::gdocs_api::_download())?;
         }
 
-        Publish2 { store, all } => {
-            publish::publish(&config, store, all)?;
+        Publish2 { store, all, drafts, as_of } => {
+            publish::publish(&config, store, all, drafts, as_of.as_deref())?;
+        }
+
+        CheckToc => {
+            publish::check_toc(&config)?;
         }
     }
 
@@ -0,0 +1,119 @@
+//! Hugo menus built from the table of contents.
+//!
+//! Pages are added to the menus listed in their `menu` column. The parent of an entry is given by
+//! the `menu_parent` column (a slug), or is the closest ancestor in the site tree that is in the
+//! same menu. The home page is not a default parent, as it's usually a sibling of top-level pages
+//! in menus. Menu entry identifiers are page slugs.
+
+use std::collections::BTreeMap;
+use crate::gdocs_site::{normalize_slug, DocData};
+use crate::site_tree::SiteTree;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MenuEntry {
+    pub identifier: String,
+    /// Page title, only in the data file (Hugo uses the page title for front matter entries)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Page URL, only in the data file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub weight: Option<i16>,
+}
+
+/// Menu entries, by menu name and page slug.
+#[derive(Debug, Default)]
+pub struct Menus {
+    menus: BTreeMap<String, BTreeMap<String, MenuEntry>>,
+}
+
+impl Menus {
+    pub fn new<'a>(docs: impl IntoIterator<Item = &'a DocData>, tree: &SiteTree) -> Menus {
+        let docs = docs.into_iter().collect::<Vec<_>>();
+        let mut menus = BTreeMap::<String, BTreeMap<String, MenuEntry>>::new();
+
+        for doc in &docs {
+            for menu in &doc.menus {
+                menus.entry(menu.clone()).or_default().insert(doc.slug.clone(), MenuEntry {
+                    identifier: doc.slug.clone(),
+                    name: Some(doc.title.clone()),
+                    url: Some(format!("{}/", doc.slug.trim_end_matches('/'))),
+                    parent: None,
+                    weight: doc.weight,
+                });
+            }
+        }
+
+        for doc in &docs {
+            for menu in &doc.menus {
+                let entries = &menus[menu];
+                let parent = match &doc.menu_parent {
+                    Some(parent) => {
+                        let parent = normalize_slug(parent);
+                        if !entries.contains_key(&parent) {
+                            println!("Warning: {} - menu parent '{}' is not in menu '{}'", doc.slug, parent, menu);
+                        }
+                        Some(parent)
+                    },
+                    None => tree.ancestors(&doc.slug)
+                        .find(|slug| *slug != "/" && entries.contains_key(*slug))
+                        .map(|s| s.to_string()),
+                };
+                menus.get_mut(menu).unwrap().get_mut(&doc.slug).unwrap().parent = parent;
+            }
+        }
+
+        Menus { menus }
+    }
+
+    /// Front matter entries of a page, by menu name.
+    pub fn page_entries(&self, slug: &str) -> BTreeMap<String, MenuEntry> {
+        self.menus.iter()
+            .filter_map(|(menu, entries)| {
+                let entry = entries.get(slug)?;
+                Some((menu.clone(), MenuEntry { name: None, url: None, ..entry.clone() }))
+            })
+            .collect()
+    }
+
+    /// All entries, by menu name, sorted by weight and name.
+    pub fn to_data(&self) -> BTreeMap<&str, Vec<&MenuEntry>> {
+        self.menus.iter()
+            .map(|(menu, entries)| {
+                let mut entries = entries.values().collect::<Vec<_>>();
+                entries.sort_by(|a, b| (a.weight, &a.name).cmp(&(b.weight, &b.name)));
+                (menu.as_str(), entries)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_menu_parents() {
+        let csv = indoc::indoc! {"
+            title,slug,menu,menu_parent,weight,publish,gdoc_url
+            Title,Slug,Menu,Parent,Weight,Publish,GDoc
+            Home,/,main,,1,TRUE,https://docs.google.com/document/d/a/edit
+            About,/about,main,,2,TRUE,https://docs.google.com/document/d/b/edit
+            Team,/about/team/people,\"main, footer\",,1,TRUE,https://docs.google.com/document/d/c/edit
+            Contact,/contact,main,about,3,TRUE,https://docs.google.com/document/d/d/edit
+        "};
+        let config: crate::config::Config = serde_yaml::from_str("hugo_site_dir: site").unwrap();
+        let docs = crate::gdocs_site::DocData::read_csv(csv.as_bytes(), &config).unwrap();
+        let tree = crate::site_tree::SiteTree::new(&docs, &config.layout, |doc| doc.publish);
+        let menus = super::Menus::new(&docs, &tree);
+
+        let parent = |menu: &str, slug: &str| menus.page_entries(slug).get(menu).and_then(|e| e.parent.clone());
+        assert_eq!(None, parent("main", "/"));
+        assert_eq!(None, parent("main", "/about"));
+        assert_eq!(Some("/about".to_string()), parent("main", "/about/team/people"));
+        assert_eq!(None, parent("footer", "/about/team/people"));
+        assert_eq!(Some("/about".to_string()), parent("main", "/contact"));
+        assert!(menus.page_entries("/about").get("footer").is_none());
+    }
+}
@@ -1,10 +1,13 @@
 use std::collections::BTreeMap;
-use crate::{config, SiteData, tweaks};
+use std::sync::Mutex;
+use crate::{config, data_sheets, drive_metadata, drive_toc, layout, sheet_pages, sheets, suggestions, BrokenLink, SiteData, tweaks};
+use crate::data_sheets::DATA_SLUG_PREFIX;
+use crate::drive_metadata::PageMetadata;
 use crate::gdocs_site;
-use crate::gdocs_site::DocData;
+use crate::gdocs_site::{DateTimeWithDefault, DocData};
 use crate::gdoc_to_html;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use anyhow::Result;
 use anyhow::Context;
 use anyhow::{anyhow, bail};
@@ -12,22 +15,27 @@ use bytes::Buf;
 use google_drive3::api::Scope;
 use hyper014::client::HttpConnector;
 use hyper_rustls::HttpsConnector;
-use indoc::indoc;
+use chrono::Utc;
 use tendril::fmt::Slice;
 use crate::gdoc_to_html::ImageReference;
+use crate::config::{BrokenLinkPolicy, Bundle, LanguageLayout, LanguagesConfig, LayoutRule, MenuOutput, StylingMode, StylesheetScope, TocReader, TocSource};
 use crate::hugo_site::FrontMatter;
 use crate::images;
+use crate::layout::Location;
+use crate::menus::Menus;
+use crate::site_tree::SiteTree;
+use crate::slug_history::SlugHistory;
+use crate::styles::StyleSheet;
 use itertools::Itertools;
 use rayon::prelude::*;
 
-#[derive(Serialize, Deserialize)]
-struct DataItem {
-    id: String,
-    #[serde(flatten)]
-    fields: BTreeMap<String, String>,
-}
+pub fn publish(config: &config::Config, store: bool, all: bool, drafts: bool, as_of: Option<&str>) -> Result<()> {
 
-pub fn publish(config: &config::Config, store: bool, all: bool) -> Result<()> {
+    let now = match as_of {
+        Some(date) => gdocs_site::parse_date(date, config)
+            .ok_or_else(|| anyhow!("Invalid date '{}'", date))?,
+        None => Utc::now().fixed_offset(),
+    };
 
     // Build a tokio runtime to call GDoc API async functions.
     // Use the default multi-threaded runtime.
@@ -43,64 +51,114 @@ pub fn publish(config: &config::Config, store: bool, all: bool) -> Result<()> {
     //----- Read ToC
 
     println!("Loading table of contents.");
-    let docs = download_toc(config, &gdrive_api, store)?;
+    let docs = download_toc(config, &gdrive_api, &gdocs_api, store)?;
+    let docs = sheet_pages::expand(docs, &gdocs_api, config)?;
 
     //----- Build site data
 
-    let site_data = SiteData::new(&docs)?;
+    let mut site_data = SiteData::new(&docs)?;
+    // Navigation links only point to pages that are on the site
+    site_data.tree = SiteTree::new(&docs, &config.layout, |doc| doc.is_live(now) || all);
+    for orphan in site_data.tree.orphans() {
+        println!("Warning: '{}' has no parent page", orphan);
+    }
+
+    let menus = Menus::new(docs.iter().filter(|doc| doc.is_live(now) || all), &site_data.tree);
+
+    let mut slug_history = SlugHistory::load(&config.download_dir)?;
+    let aliases = slug_history.update(&docs);
+
+    let site_styles = Mutex::new(StyleSheet::default());
+    let broken_links = Mutex::new(Vec::<BrokenLink>::new());
 
     //----- Publish docs
 
     docs.into_par_iter()
         .map(|site_doc| {
-            if !site_doc.publish && !all {
-                println!("Skipping '{}' (not published)", site_doc.slug);
+            let has_body = sheet_pages::has_body(&site_doc);
+            let draft = !all && is_draft(&site_doc, drafts);
+
+            if let Some(reason) = site_doc.unpublished_reason(now).filter(|_| !all && !draft) {
+                println!("Skipping '{}' ({})", site_doc.slug, reason);
+                if site_doc.publish {
+                    // Scheduled or expired: remove pages from a previous run
+                    remove_doc(&site_doc, &config.layout, &site_data, &config.hugo_site_dir, &config.languages)?;
+                }
                 return Ok(());
             }
 
             let _guard = rt.enter();
 
-            if site_doc.slug.starts_with("/#data/") {
-
-                let sheet_id = gdocs_site::get_doc_id(site_doc.gdoc_url.as_ref().unwrap())
-                    .ok_or_else(|| anyhow!("Failed to extract doc id from {:?}", site_doc.gdoc_url))?;
-
-                let bytes: bytes::Bytes = tokio::runtime::Handle::current().block_on(async {
-                    let mut response = gdrive_api.files()
-                        .export(sheet_id, "text/csv")
-                        .add_scope(Scope::Readonly) // Otherwise we'll get a 404
-                        .doit().await?;
-                    let bytes = hyper014::body::to_bytes(response.body_mut()).await?;
-                    <Result<_>>::Ok(bytes)
-                })
-                    .context("Failed to download data document")?;
-
-                // let mut data = DataItem::read_csv(bytes.clone().reader())
-                //     .context("Problem reading ToC spreadsheet")?;
-
-                let mut rdr = csv::ReaderBuilder::new().from_reader(bytes.reader());
-
-                let items = rdr.deserialize()
-                    // First line after the header is the human-readable column names: skip it
-                    .skip(1)
-                    .map_ok(|data: DataItem| {
-                        (data.id, data.fields)
-                    })
-                    .collect::<csv::Result<BTreeMap<String, BTreeMap<String, String>>>>()?;
-
-                let path = config.hugo_site_dir.join(&site_doc.slug[2..]).with_extension("yml");
-
-                serde_yaml::to_writer(std::fs::File::create(&path)?, &items)?;
-
-                println!("Saved data file to {:?}", path);
-                return Ok(());
+            if site_doc.slug.starts_with(DATA_SLUG_PREFIX) {
+                return data_sheets::publish_data_sheet(&site_doc, &gdrive_api, &gdocs_api, config);
             }
 
-            //----- Load doc JSON
-            let gdoc = download_gdoc_json(&site_doc, &config, &gdocs_api, &rt, store)?;
+            let location = layout::locate(&site_doc, &config.layout);
+
+            let (html, styles, doc_id) = if has_body {
+                //----- Page generated from a spreadsheet row
+                let mut warnings = Vec::new();
+                let html = sheet_pages::body_html(&site_doc, config, &mut warnings);
+                for warning in &warnings {
+                    println!("Warning: {} - {}", site_doc.slug, warning);
+                }
+                (html, StyleSheet::default(), None)
+
+            } else if let Some(revision) = &site_doc.revision {
+                //----- Approved revision, exported as HTML
+                // The Docs API only returns the live doc, so revisions are rendered from Drive's
+                // HTML export, with the same shortcode, styling and sanitization options.
+                let doc_id = site_doc.gdoc_url.as_deref().and_then(gdocs_site::get_doc_id)
+                    .ok_or_else(|| anyhow!("{} - No GDoc URL in table of contents", site_doc.slug))?
+                    .to_owned();
+
+                let pinned = drive_metadata::pinned_revision(&gdrive_api, &doc_id, revision)
+                    .with_context(|| format!("{} - Failed to find revision '{}'", site_doc.slug, revision))?;
+                if let Some(time) = pinned.edited_since {
+                    println!("Warning: {} - doc edited on {} since revision '{}' was approved", site_doc.slug, time, pinned.id);
+                }
+
+                println!("Downloading revision '{}' for page at {} (id={})", pinned.id, &site_doc.slug, &doc_id);
+                let (_, bytes) = rt.block_on(download_url(&gdocs_api, &pinned.html_url))
+                    .with_context(|| format!("{} - Failed to download revision '{}'", site_doc.slug, pinned.id))?;
+
+                let sanitize = &config.sanitize;
+                let options = gdoc_to_html::RenderOptions {
+                    styling: Some(&config.styling),
+                    sanitize: (sanitize.enabled && !site_doc.trusted).then_some(sanitize),
+                };
+                let rendered = gdoc_to_html::render_export(&String::from_utf8_lossy(&bytes), &options)?;
+                for warning in &rendered.warnings {
+                    println!("Warning: {} - {}", site_doc.slug, warning);
+                }
+
+                (rendered.html, rendered.styles, Some(doc_id))
+
+            } else {
+                //----- Load doc JSON
+                let gdoc = download_gdoc_json(&site_doc, &config, &gdocs_api, &rt, store)?;
+
+                //----- Convert doc JSON to HTML and DOM
+                let sanitize = &config.sanitize;
+                let options = gdoc_to_html::RenderOptions {
+                    styling: Some(&config.styling),
+                    sanitize: (sanitize.enabled && !site_doc.trusted).then_some(sanitize),
+                };
+                let rendered = gdoc_to_html::render_with(&gdoc, &options)?;
+                for warning in &rendered.warnings {
+                    println!("Warning: {} - {}", site_doc.slug, warning);
+                }
+
+                let doc_id = gdocs_site::get_doc_id(site_doc.gdoc_url.as_ref().unwrap())
+                    .unwrap().to_owned();
+
+                (rendered.html, rendered.styles, Some(doc_id))
+            };
 
-            //----- Convert doc JSON to HTML and DOM
-            let html = gdoc_to_html::render(&gdoc)?;
+            let metadata = match &doc_id {
+                Some(doc_id) => drive_metadata::page_metadata(&gdrive_api, doc_id, config)?,
+                None => PageMetadata::default(),
+            };
 
             if store {
                 let doc_path = &config.download_dir
@@ -115,46 +173,180 @@ pub fn publish(config: &config::Config, store: bool, all: bool) -> Result<()> {
             let mut dom = scraper::Html::parse_document(&html);
 
             //----- Prepare Front matter
-            let categories: Vec<String> = if let Some(category) = site_doc.category {
-                vec![category]
-            } else {
-                Vec::new()
+            let flat_slug = site_doc.slug.replace('/', "_");
+
+            let page_menus = match config.menus.output {
+                MenuOutput::FrontMatter => menus.page_entries(&site_doc.slug),
+                MenuOutput::DataFile => BTreeMap::new(),
             };
 
-            let flat_slug = site_doc.slug.replace('/', "_");
-            let doc_id = gdocs_site::get_doc_id(site_doc.gdoc_url.as_ref().unwrap())
-                .unwrap().to_owned();
+            let nav = &config.navigation;
+            let tree = &site_data.tree;
+            let breadcrumbs = if nav.breadcrumbs { tree.breadcrumbs(&site_doc.slug) } else { Vec::new() };
+            let (prev, next) = if nav.prev_next { tree.prev_next(&site_doc.slug) } else { (None, None) };
+            let children = if nav.children { tree.child_links(&site_doc.slug) } else { Vec::new() };
 
             let mut fm = FrontMatter {
                 markup: "html",
+                // Title of GDocs is their <h1>
+                title: if has_body { site_doc.title } else { String::new() },
+                draft,
                 date: site_doc.publish_date,
-                lastmod: site_doc.update_date,
-                author: site_doc.author,
+                lastmod: site_doc.update_date.or(metadata.lastmod.map(DateTimeWithDefault)),
+                expiry_date: site_doc.expiry_date,
+                author: site_doc.author.or(metadata.author).or_else(|| config.default_author.clone()),
+                contributors: metadata.contributors,
                 slug: flat_slug,
+                aliases: aliases.get(&site_doc.slug).cloned().unwrap_or_default(),
                 url: Some(site_doc.slug),
                 gdoc_url: site_doc.gdoc_url,
                 weight: site_doc.weight,
-                categories,
+                menus: page_menus,
+                breadcrumbs,
+                prev,
+                next,
+                children,
+                lang: site_doc.lang,
+                translation_key: site_doc.translation_key,
+                categories: site_doc.categories,
+                tags: site_doc.tags,
+                taxonomies: site_doc.taxonomies,
+                summary: site_doc.summary,
                 other: site_doc.other,
                 ..FrontMatter::default()
             };
 
+            if !styles.is_empty() {
+                match config.styling.stylesheet {
+                    StylesheetScope::Page => fm.inline_style = Some(styles.to_css()),
+                    StylesheetScope::Site => site_styles.lock().unwrap().merge(styles),
+                }
+            }
+
             //----- Apply tweaks
 
-            tweak_dom(&gdocs_api, &doc_id, &mut dom, &mut fm, &site_data, &config, store)
-                .with_context(|| format!("GDoc id: {}", &doc_id))?;
+            let doc_broken_links = match &doc_id {
+                Some(doc_id) => tweak_dom(&gdocs_api, &mut dom, &mut fm, &location, &site_data, &config, store)
+                    .with_context(|| format!("GDoc id: {}", doc_id))?,
+                None => {
+                    tweaks::extract_summary(&mut dom, &mut fm, &config.summary);
+                    tweaks::rewrite_links(&mut dom, &site_data, &config.links, fm.url.as_ref().unwrap(), fm.lang.as_deref())?
+                },
+            };
+
+            let has_broken_links = !doc_broken_links.is_empty();
+            broken_links.lock().unwrap().extend(doc_broken_links);
+            if has_broken_links && config.links.broken == BrokenLinkPolicy::Error {
+                println!("Not writing '{}' (broken links)", fm.url.as_ref().unwrap());
+                return Ok(());
+            }
 
             //----- And store to its final location
 
-            let hugo_dir = &config.hugo_site_dir;
-
-            write_doc(&dom, &fm, &site_data, hugo_dir)?;
+            write_doc(&dom, &fm, &location, &site_data, config)?;
 
             Ok(())
 
         })
         .collect::<anyhow::Result<Vec<()>>>()?;
 
+    //----- Write the site-wide stylesheet
+
+    if config.styling.mode == StylingMode::Classes && config.styling.stylesheet == StylesheetScope::Site {
+        let css_path = config.hugo_site_dir.join(&config.styling.stylesheet_path);
+        fs::create_dir_all(css_path.parent().unwrap())?;
+        fs::write(&css_path, site_styles.into_inner().unwrap().to_css())
+            .with_context(|| format!("Cannot write to {:?}", &css_path))?;
+        println!("Saved stylesheet to {:?}", css_path);
+    }
+
+    slug_history.save()?;
+
+    //----- Write the menus data file
+
+    if config.menus.output == MenuOutput::DataFile {
+        let menu_path = config.hugo_site_dir.join(&config.menus.data_file);
+        fs::create_dir_all(menu_path.parent().unwrap())?;
+        serde_yaml::to_writer(fs::File::create(&menu_path)?, &menus.to_data())
+            .with_context(|| format!("Cannot write to {:?}", &menu_path))?;
+        println!("Saved menus to {:?}", menu_path);
+    }
+
+    //----- Report broken links
+
+    let broken_links = broken_links.into_inner().unwrap();
+    if !broken_links.is_empty() {
+        report_broken_links(&broken_links, config, &gdrive_api)?;
+
+        if config.links.broken == BrokenLinkPolicy::Error {
+            bail!("Found {} links to GDocs that are not in the page list", broken_links.len());
+        }
+    }
+
+    Ok(())
+}
+
+/// Unpublished docs are drafts in drafts mode, if they are pages with content. Data rows have no
+/// draft state in Hugo and are never published as drafts.
+fn is_draft(doc: &DocData, drafts: bool) -> bool {
+    drafts && !doc.publish
+        && !doc.slug.starts_with(DATA_SLUG_PREFIX)
+        && (doc.gdoc_url.is_some() || sheet_pages::has_body(doc))
+}
+
+#[derive(Serialize)]
+struct BrokenLinkRow<'a> {
+    page: &'a str,
+    link_text: &'a str,
+    doc_id: &'a str,
+    doc_title: &'a str,
+    url: &'a str,
+}
+
+//--------------------------------------------------------------------------------------------------
+///
+/// Print the list of links to GDocs that are not in the table of contents, and save it as a CSV
+/// file in the download directory so that editors can fix them in batch.
+///
+fn report_broken_links(broken_links: &[BrokenLink], config: &config::Config, gdrive: &google_drive3::DriveHub<HyperC>) -> Result<()> {
+
+    // Target docs are not in the ToC, but may be readable: get their title
+    let titles = broken_links.iter()
+        .filter_map(|link| link.target.doc_id.as_deref())
+        .unique()
+        .map(|id| {
+            let title = tokio::runtime::Handle::current()
+                .block_on(gdrive.files().get(id).param("fields", "name").add_scope(Scope::Readonly).doit())
+                .ok()
+                .and_then(|(_, file)| file.name)
+                .unwrap_or_else(|| "(not accessible)".to_string());
+            (id, title)
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    let report_path = config.download_dir.join("broken-links.csv");
+    fs::create_dir_all(&config.download_dir)?;
+    let mut wtr = csv::Writer::from_path(&report_path)
+        .with_context(|| format!("Cannot write to {:?}", &report_path))?;
+
+    println!("Links to GDocs that are not in the page list:");
+    for link in broken_links {
+        let doc_id = link.target.doc_id.as_deref().unwrap_or("");
+        let doc_title = titles.get(doc_id).map_or("", |t| t.as_str());
+        println!("  {} - '{}' -> '{}' ({})", link.page, link.text, doc_title, link.target.url);
+
+        wtr.serialize(BrokenLinkRow {
+            page: &link.page,
+            link_text: &link.text,
+            doc_id,
+            doc_title,
+            url: &link.target.url,
+        })?;
+    }
+    wtr.flush()?;
+
+    println!("Saved broken links report to {:?}", report_path);
+
     Ok(())
 }
 
@@ -162,23 +354,8 @@ pub fn publish(config: &config::Config, store: bool, all: bool) -> Result<()> {
 ///
 /// Write doc
 ///
-pub fn write_doc(dom: &scraper::Html, fm: &FrontMatter, site_data: &SiteData, hugo_dir: impl AsRef<Path>) -> Result<()> {
-    let hugo_dir = hugo_dir.as_ref().to_owned();
-    let doc_slug = fm.url.as_ref().unwrap();
-    let post_path = if fm.categories.is_empty() {
-        // See https://gohugo.io/content-management/page-bundles/
-        let has_children = site_data.id_to_slug.values()
-            .any(|s| s.len() != doc_slug.len() && s.starts_with(doc_slug));
-        if has_children {
-            // Branch bundle
-            hugo_dir.join(format!("content{}/_index.html", doc_slug))
-        } else {
-            // Leaf page
-            hugo_dir.join(format!("content{}/index.html", doc_slug))
-        }
-    } else {
-        hugo_dir.join(format!("content/posts{}.html", doc_slug))
-    };
+pub fn write_doc(dom: &scraper::Html, fm: &FrontMatter, location: &Location, site_data: &SiteData, config: &config::Config) -> Result<()> {
+    let post_path = doc_path(fm.url.as_ref().unwrap(), location, fm.lang.as_deref(), site_data, &config.hugo_site_dir, &config.languages);
 
     let cleaned_html = crate::from_web_pub::serialize::stable_html(&dom)?;
     println!("Writing {:?}", &post_path);
@@ -187,53 +364,105 @@ pub fn write_doc(dom: &scraper::Html, fm: &FrontMatter, site_data: &SiteData, hu
 
     fs::write(
         &post_path,
-        format!(
-            indoc! {r#"
-                ---
-                {}
-                ---
-
-                {}
-            "#},
-            serde_yaml::to_string(&fm)?,
-            &cleaned_html
-        ),
+        format!("{}\n{}\n", fm.render(&config.front_matter)?, &cleaned_html),
     ).with_context(|| format!("Cannot write to {:?}", &post_path))?;
 
     Ok(())
 }
 
+/// Remove the page written for a doc, if any. Its resources (images, etc) are left untouched.
+pub fn remove_doc(doc: &DocData, layout: &[LayoutRule], site_data: &SiteData, hugo_dir: impl AsRef<Path>, languages: &LanguagesConfig) -> Result<()> {
+    let location = layout::locate(doc, layout);
+    let post_path = doc_path(&doc.slug, &location, doc.lang.as_deref(), site_data, hugo_dir, languages);
+    if post_path.exists() {
+        fs::remove_file(&post_path).with_context(|| format!("Cannot remove {:?}", &post_path))?;
+        println!("Removed {:?}", &post_path);
+    }
+    Ok(())
+}
+
+/// Path of the page written for a doc.
+fn doc_path(doc_slug: &str, location: &Location, lang: Option<&str>, site_data: &SiteData, hugo_dir: impl AsRef<Path>, languages: &LanguagesConfig) -> PathBuf {
+    let content_dir = content_dir(hugo_dir, languages, lang);
+
+    // Translation by filename: the language is in the file name, except for the default language
+    let lang_suffix = match lang {
+        Some(lang) if languages.layout == LanguageLayout::Filenames && Some(lang) != languages.default.as_deref() => {
+            format!(".{}", lang)
+        },
+        _ => String::new(),
+    };
+
+    // See https://gohugo.io/content-management/page-bundles/
+    let bundle = match location.bundle {
+        Bundle::Auto if site_data.tree.has_children(doc_slug) => Bundle::Branch,
+        Bundle::Auto => Bundle::Leaf,
+        bundle => bundle,
+    };
+
+    let path = &location.path[1..];
+    match bundle {
+        Bundle::Branch => content_dir.join(format!("{}/_index{}.html", path, lang_suffix)),
+        Bundle::File => content_dir.join(format!("{}{}.html", path, lang_suffix)),
+        _ => content_dir.join(format!("{}/index{}.html", path, lang_suffix)),
+    }
+}
+
+/// Hugo content directory for a language. With the `dirs` layout, each language has its own
+/// content directory, `content/<lang>`.
+pub fn content_dir(hugo_dir: impl AsRef<Path>, languages: &LanguagesConfig, lang: Option<&str>) -> PathBuf {
+    let content_dir = hugo_dir.as_ref().join("content");
+    match lang {
+        Some(lang) if languages.layout == LanguageLayout::Dirs => content_dir.join(lang),
+        _ => content_dir,
+    }
+}
+
 //--------------------------------------------------------------------------------------------------
 ///
-/// Tweak the raw document, extracting front-matter information, downloading images, etc
+/// Tweak the raw document, extracting front-matter information, downloading images, etc. Images
+/// and Drive files are stored in the page's resource directory (see [`resource_dir`]).
 ///
-pub fn tweak_dom(gdocs_api: &google_docs1::Docs<HyperC>, _doc_id: &str, dom: &mut scraper::Html, fm: &mut FrontMatter, site_data: &SiteData, config: &config::Config, store: bool) -> Result<()> {
+pub fn tweak_dom(gdocs_api: &google_docs1::Docs<HyperC>, dom: &mut scraper::Html, fm: &mut FrontMatter, location: &Location, site_data: &SiteData, config: &config::Config, store: bool) -> Result<Vec<BrokenLink>> {
 
     tweaks::remove_head(dom);
 
+    let content_dir = content_dir(&config.hugo_site_dir, &config.languages, fm.lang.as_deref());
+
     tweaks::import_img_elts(dom, |img| download_image(
             gdocs_api,
             img,
             fm.url.as_ref().unwrap(),
-            &config.hugo_site_dir,
+            location,
+            &content_dir,
             store.then(|| config.download_dir.as_path()))
     )?;
 
-    tweaks::rewrite_links(dom, site_data)?;
+    tweaks::import_drive_links(dom, |file_id| download_drive_file(
+            gdocs_api,
+            file_id,
+            fm.url.as_ref().unwrap(),
+            location,
+            &content_dir,
+            &config.drive_links)
+    )?;
+
+    let broken_links = tweaks::rewrite_links(dom, site_data, &config.links, fm.url.as_ref().unwrap(), fm.lang.as_deref())?;
 
     // Must be done last, after image and link URL rewriting
-    tweaks::extract_title_and_summary(dom, fm)?;
+    tweaks::extract_title_and_summary(dom, fm, &config.summary)?;
 
     tweaks::move_bootstrap_btn_classes(dom)?;
 
-    Ok(())
+    Ok(broken_links)
 }
 
 pub fn download_image(
     gdocs_api: &google_docs1::Docs<HyperC>,
     img: &ImageReference,
     url: &str,
-    site_dir: impl AsRef<Path>,
+    location: &Location,
+    content_dir: impl AsRef<Path>,
     store_path: Option<&Path>
 ) -> Result<String> {
 
@@ -243,7 +472,7 @@ pub fn download_image(
     //         .with_extension(".json");
     // }
 
-    let base_path = site_dir.as_ref().join("content").join(&url[1..]).join(img.id);
+    let base_path = resource_dir(url, location, content_dir).join(img.id);
 
     let extension = images::download_and_store(gdocs_api, img.src, base_path, |_img_bytes, extension| {
         if let Some(path) = store_path {
@@ -255,29 +484,193 @@ pub fn download_image(
         }
     })?;
 
-    // Image is stored in the page's directory, so return a relative url
-    Ok(format!("{}/{}.{}", url, img.id, extension))
+    Ok(format!("{}/{}.{}", url.trim_end_matches('/'), img.id, extension))
+
+}
+
+/// Download a Drive file linked from a page to the page's location, if its mime type is listed in
+/// the configuration. Google files (Sheets, Slides, etc) are exported to the configured format.
+///
+/// Returns the new link URL, or `None` if the file was not downloaded.
+pub fn download_drive_file(
+    gdocs_api: &google_docs1::Docs<HyperC>,
+    file_id: &str,
+    url: &str,
+    location: &Location,
+    content_dir: impl AsRef<Path>,
+    drive_links: &config::DriveLinksConfig,
+) -> Result<Option<String>> {
+
+    const FILES_API: &str = "https://www.googleapis.com/drive/v3/files";
+
+    if drive_links.download.is_empty() {
+        return Ok(None);
+    }
+
+    let rt = tokio::runtime::Handle::current();
+
+    // Files that can't be read (e.g. not shared with the site's account) keep their link
+    let metadata_url = format!("{}/{}?fields=name,mimeType&supportsAllDrives=true", FILES_API, file_id);
+    let file = rt.block_on(download_url(gdocs_api, &metadata_url))
+        .and_then(|(_, bytes)| Ok(serde_json::from_slice::<google_drive3::api::File>(&bytes)?));
+    let file = match file {
+        Ok(file) => file,
+        Err(err) => {
+            println!("Warning: {} - Cannot read metadata of Drive file {}, keeping the link: {}", url, file_id, err);
+            return Ok(None);
+        }
+    };
+
+    let mime_type = file.mime_type.unwrap_or_default();
+    if !drive_links.download.contains(&mime_type) {
+        return Ok(None);
+    }
+
+    let file_url = if mime_type.starts_with("application/vnd.google-apps.") {
+        let export_type = drive_links.export.get(&mime_type)
+            .ok_or_else(|| anyhow!("No export format for Drive file {} of type {}", file_id, mime_type))?;
+        let export_type = url::form_urlencoded::byte_serialize(export_type.as_bytes()).collect::<String>();
+        format!("{}/{}/export?mimeType={}", FILES_API, file_id, export_type)
+    } else {
+        format!("{}/{}?alt=media&supportsAllDrives=true", FILES_API, file_id)
+    };
+
+    let (extension, bytes) = rt.block_on(download_url(gdocs_api, &file_url))
+        .with_context(|| format!("Failed to download Drive file {}", file_id))?;
+
+    let name = drive_file_name(file.name.as_deref(), file_id);
+
+    let file_path = resource_dir(url, location, content_dir).join(&name).with_extension(extension);
+    fs::create_dir_all(file_path.parent().unwrap())?;
+    fs::write(&file_path, &bytes)
+        .with_context(|| format!("Cannot write to {:?}", &file_path))?;
 
+    println!("Downloaded Drive file to {:?}", file_path);
+
+    Ok(Some(format!("{}/{}.{}", url.trim_end_matches('/'), name, extension)))
+}
+
+/// Directory where the images and files of a page are stored, so that they're published below the
+/// page's url. Bundles publish their resources at the page's url. Single-file pages have no bundle:
+/// their resources are stored in a directory at the page's url, and published as-is by Hugo.
+fn resource_dir(url: &str, location: &Location, content_dir: impl AsRef<Path>) -> PathBuf {
+    match location.bundle {
+        Bundle::File => content_dir.as_ref().join(&url[1..]),
+        _ => content_dir.as_ref().join(&location.path[1..]),
+    }
+}
+
+/// Local name of a downloaded Drive file: its name without extension, followed by its id so that
+/// files with the same name don't overwrite each other, nor the page's own `index.html`.
+fn drive_file_name(name: Option<&str>, file_id: &str) -> String {
+    let stem = name
+        .and_then(|name| Path::new(name).file_stem())
+        .map(|stem| gdocs_site::slugify(&stem.to_string_lossy()))
+        .unwrap_or_default();
+
+    if stem.is_empty() {
+        file_id.to_string()
+    } else {
+        format!("{}-{}", stem, file_id)
+    }
 }
+
 //--------------------------------------------------------------------------------------------------
 ///
-/// Download the site's table of content from the CVS export of the ToC spreadsheet
+/// Load and validate the table of contents. Problems are reported as a `TocProblems` error.
 ///
-pub fn download_toc (config: &config::Config, gdrive: &google_drive3::DriveHub<HyperC>, store: bool) -> Result<Vec<DocData>> {
+pub fn check_toc(config: &config::Config) -> Result<()> {
+
+    let rt = tokio::runtime::Runtime::new()?;
+    let _guard = rt.enter();
+
+    let config_path = &config.credentials.as_ref().ok_or(anyhow!("Missing credentials in config"))?;
+    let gdrive_api = rt.block_on(create_gdrive_client(config_path))?;
+    let gdocs_api = rt.block_on(create_gdocs_client(config_path))?;
+
+    let docs = download_toc(config, &gdrive_api, &gdocs_api, false)?;
+    let docs = sheet_pages::expand(docs, &gdocs_api, config)?;
+
+    for orphan in SiteTree::new(&docs, &config.layout, |doc| doc.publish).orphans() {
+        println!("Warning: '{}' has no parent page", orphan);
+    }
 
-    let toc_id = gdocs_site::get_doc_id(&config.toc_spreadsheet_url)
-        .ok_or_else(|| anyhow!("Cannot extract ToC doc id from {}", config.toc_spreadsheet_url))?;
+    let published = docs.iter().filter(|doc| doc.publish).count();
+    println!("No problem found in the table of contents ({} pages, {} published).", docs.len(), published);
+
+    Ok(())
+}
+
+//--------------------------------------------------------------------------------------------------
+///
+/// Download the site's table of content, from the ToC spreadsheet or a Drive folder
+///
+pub fn download_toc (config: &config::Config, gdrive: &google_drive3::DriveHub<HyperC>, gdocs_api: &google_docs1::Docs<HyperC>, store: bool) -> Result<Vec<DocData>> {
+
+    let mut docs = match config.toc_source {
+        TocSource::Sheet => download_toc_sheet(config, gdrive, gdocs_api, store)?,
+        TocSource::DriveFolder => {
+            let folder_url = config.toc_folder_url.as_ref()
+                .ok_or_else(|| anyhow!("Missing toc_folder_url in config"))?;
+            drive_toc::read_folder_toc(gdrive, folder_url, config)?
+        }
+    };
+
+    // With authors from Drive, the default author is set when publishing
+    if !config.drive_metadata.author {
+        for doc in &mut docs {
+            if doc.author.is_none() {
+                doc.author = config.default_author.clone();
+            }
+        }
+    }
+
+    Ok(docs)
+}
+
+/// Download the site's table of content from the ToC spreadsheet, using either the Sheets API or
+/// its CSV export.
+fn download_toc_sheet(config: &config::Config, gdrive: &google_drive3::DriveHub<HyperC>, gdocs_api: &google_docs1::Docs<HyperC>, store: bool) -> Result<Vec<DocData>> {
+
+    let toc_url = config.toc_spreadsheet_url.as_ref()
+        .ok_or_else(|| anyhow!("Missing toc_spreadsheet_url in config"))?;
+
+    let toc_id = gdocs_site::get_doc_id(toc_url)
+        .ok_or_else(|| anyhow!("Cannot extract ToC doc id from {}", toc_url))?;
+
+    if config.toc_reader == TocReader::Api {
+        println!("Reading ToC from GSheet id={}", toc_id);
+
+        match sheets::read_rows(gdocs_api, toc_id, config.toc_sheet.as_deref()) {
+            Ok(rows) => {
+                if store {
+                    fs::create_dir_all(&config.download_dir)?;
+                    let toc_path = config.download_dir.join("pages.csv");
+                    let mut wtr = csv::Writer::from_path(&toc_path)
+                        .with_context(|| format!("Failed to write ToC spreadsheet {:?}", &toc_path))?;
+                    for row in &rows {
+                        wtr.write_record(row)?;
+                    }
+                    wtr.flush()?;
+
+                    println!("Saved table of contents to {:?}", toc_path);
+                }
+
+                let docs = DocData::read_sheet_rows(&rows, config)
+                    .context("Problem reading ToC spreadsheet")?;
+                return Ok(docs);
+            },
+            Err(err) if config.toc_sheet.is_none() => {
+                // The CSV export only contains the first tab
+                println!("Warning: failed to read ToC with the Sheets API, using CSV export: {:#}", err);
+            },
+            Err(err) => return Err(err),
+        }
+    }
 
     println!("Downloading ToC from GSheet id={}", toc_id);
 
-    let bytes: bytes::Bytes = tokio::runtime::Handle::current().block_on(async {
-            let mut response = gdrive.files()
-                .export(toc_id, "text/csv")
-                .add_scope(Scope::Readonly) // Otherwise we'll get a 404
-                .doit().await?;
-            let bytes = hyper014::body::to_bytes(response.body_mut()).await?;
-            <Result<_>>::Ok(bytes)
-        })
+    let bytes = export_csv(gdrive, toc_id)
         .context("Failed to download ToC spreadsheet")?;
 
 
@@ -291,18 +684,24 @@ pub fn download_toc (config: &config::Config, gdrive: &google_drive3::DriveHub<H
         println!("Saved table of contents to {:?}", toc_path);
     }
 
-    let mut docs = DocData::read_csv(bytes.clone().reader())
+    let docs = DocData::read_csv(bytes.clone().reader(), config)
         .context("Problem reading ToC spreadsheet")?;
 
-    for doc in &mut docs {
-        if doc.author.is_none() {
-            doc.author = config.default_author.clone();
-        }
-    }
-
     Ok(docs)
 }
 
+/// Download the CSV export of a spreadsheet, which only contains its first tab.
+pub fn export_csv(gdrive: &google_drive3::DriveHub<HyperC>, spreadsheet_id: &str) -> Result<bytes::Bytes> {
+    tokio::runtime::Handle::current().block_on(async {
+        let mut response = gdrive.files()
+            .export(spreadsheet_id, "text/csv")
+            .add_scope(Scope::Readonly) // Otherwise we'll get a 404
+            .doit().await?;
+        let bytes = hyper014::body::to_bytes(response.body_mut()).await?;
+        Ok(bytes)
+    })
+}
+
 //--------------------------------------------------------------------------------------------------
 ///
 /// Create the Google Docs client
@@ -403,8 +802,14 @@ pub async fn download_url(hub: &google_docs1::Docs<HyperC>, url: &str) -> anyhow
         bail!("HTTP failure: {} for {}", response.status(), url);
     }
 
-    let content_type = response.headers().get(CONTENT_TYPE).unwrap().to_str().unwrap();
-    let mut extension = mime_guess::get_mime_extensions_str(content_type).unwrap()[0];
+    let content_type = response.headers().get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    // Remove parameters, e.g. "; charset=UTF-8"
+    let content_type = content_type.split(';').next().unwrap_or_default().trim();
+    let mut extension = mime_guess::get_mime_extensions_str(content_type)
+        .and_then(|extensions| extensions.first().copied())
+        .ok_or_else(|| anyhow!("Unknown content type '{}' for {}", content_type, url))?;
     if extension == "jpe" {
         // jpe is the first extension listed for jpeg. Although it's a valid extension, not all
         // tools recognize it.
@@ -434,11 +839,29 @@ pub fn download_gdoc_json (
     let doc_id = gdocs_site::get_doc_id(url)
         .ok_or_else(|| anyhow!("{} - URL is not a GDoc: {}", site_doc.slug, url))?;
 
-    let gdoc = rt.block_on(gdocs_api.documents().get(doc_id).doit())
+    println!("Downloading GDoc for page at {} (id={})", &site_doc.slug, &doc_id);
+
+    // Open suggestions are only visible inline. Without any, all view modes give the same doc,
+    // which is used as is.
+    let view_mode = if config.suggestions.refuse_open {
+        "SUGGESTIONS_INLINE"
+    } else {
+        config.suggestions.view_mode.api_value()
+    };
+
+    let gdoc = rt.block_on(gdocs_api.documents().get(doc_id).suggestions_view_mode(view_mode).doit())
         .with_context(|| format!("{} - Failed to load document.", site_doc.slug))?
         .1;
 
-    println!("Downloading GDoc for page at {} (id={})", &site_doc.slug, &doc_id);
+    if config.suggestions.refuse_open {
+        let suggestions = suggestions::open_suggestions(&gdoc);
+        if !suggestions.is_empty() {
+            let list = suggestions.iter()
+                .map(|(id, changes)| format!("{}: {}", id, changes.join(", ")))
+                .join("\n  - ");
+            bail!("{} - Not published, {} open suggestion(s):\n  - {}", site_doc.slug, suggestions.len(), list);
+        }
+    }
 
     if store {
         let json_path = config.download_dir
@@ -459,3 +882,58 @@ fn rel_path_or_index(slug: &str) -> &str {
         &slug[1..]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_is_draft() {
+        let csv = indoc::indoc! {"
+            title,slug,gdoc_url,body,publish
+            Title,Slug,GDoc,Body,Publish
+            Page,/page,https://docs.google.com/document/d/abc/edit,,FALSE
+            Row page,/row,,Some text,FALSE
+            Empty,/empty,,,FALSE
+            Prices,/#data/prices,https://docs.google.com/spreadsheets/d/xyz/edit,,FALSE
+            Live,/live,https://docs.google.com/document/d/def/edit,,TRUE
+        "};
+        let config = serde_yaml::from_str("hugo_site_dir: site").unwrap();
+        let docs = crate::gdocs_site::DocData::read_csv(csv.as_bytes(), &config).unwrap();
+
+        let drafts = docs.iter().filter(|doc| super::is_draft(doc, true)).map(|doc| doc.slug.as_str()).collect::<Vec<_>>();
+        assert_eq!(vec!["/page", "/row"], drafts);
+        assert!(docs.iter().all(|doc| !super::is_draft(doc, false)));
+    }
+
+    #[test]
+    fn test_drive_file_name() {
+        assert_eq!("price-list-abc", super::drive_file_name(Some("Price list.pdf"), "abc"));
+        assert_eq!("index-abc", super::drive_file_name(Some("index.html"), "abc"));
+        assert_eq!("abc", super::drive_file_name(None, "abc"));
+        assert_ne!(super::drive_file_name(Some("Report.pdf"), "abc"), super::drive_file_name(Some("Report.pdf"), "def"));
+    }
+
+    #[test]
+    fn test_resource_dir() {
+        use std::path::Path;
+        let csv = indoc::indoc! {"
+            title,slug,category,publish
+            Title,Slug,Category,Publish
+            Launch,/launch,News,FALSE
+            About,/about,,FALSE
+        "};
+        let config: crate::config::Config = serde_yaml::from_str("hugo_site_dir: site").unwrap();
+        let docs = crate::gdocs_site::DocData::read_csv(csv.as_bytes(), &config).unwrap();
+        let content_dir = Path::new("site/content");
+
+        // Categorized pages are posts, stored as single files in content/posts. Their images are
+        // stored where the image link points to.
+        let location = crate::layout::locate(&docs[0], &config.layout);
+        assert_eq!(Path::new("site/content/posts/launch.html"), super::doc_path("/launch", &location, None, &Default::default(), "site", &config.languages));
+        let img_path = super::resource_dir("/launch", &location, content_dir).join("kix.abc.png");
+        assert_eq!(Path::new("site/content/launch/kix.abc.png"), img_path);
+
+        // Bundles have their images next to their index
+        let location = crate::layout::locate(&docs[1], &config.layout);
+        assert_eq!(Path::new("site/content/about"), super::resource_dir("/about", &location, content_dir));
+    }
+}
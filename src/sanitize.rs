@@ -0,0 +1,212 @@
+//! Allowlist-based sanitization of HTML written by document authors.
+//!
+//! Injected HTML (the `{{ html ... }}` shortcode) is usually a fragment with unbalanced tags, e.g.
+//! `<div class='row'>` in one paragraph and `</div>` a few paragraphs below. It therefore cannot
+//! be parsed as a DOM tree, and is sanitized tag by tag instead.
+
+use lazy_regex::regex;
+use crate::config::SanitizeConfig;
+
+/// Elements whose content must be removed along with them.
+const RAW_TEXT_ELEMENTS: &[&str] = &["script", "style", "textarea", "title", "xmp", "noscript"];
+
+/// Attributes that contain URLs.
+const URL_ATTRIBUTES: &[&str] = &["href", "src", "action", "formaction", "poster", "cite", "srcset", "xlink:href"];
+
+/// Sanitize an HTML fragment, removing elements, attributes and URLs that are not in the allowlist.
+/// A description of removed content is added to `warnings`.
+pub fn clean_html(config: &SanitizeConfig, html: &str, warnings: &mut Vec<String>) -> String {
+    let token_re = regex!(r#"<!--.*?-->|</?([A-Za-z][A-Za-z0-9:-]*)((?:\s+[^\s"'>/=]+(?:\s*=\s*(?:"[^"]*"|'[^']*'|[^\s"'=<>`]+))?)*)\s*/?>"#s);
+
+    let mut result = String::new();
+    let mut pos = 0;
+    // Raw text element being skipped, if any
+    let mut skip_until: Option<String> = None;
+
+    for captures in token_re.captures_iter(html) {
+        let token = captures.get(0).unwrap();
+        let text = &html[pos..token.start()];
+        pos = token.end();
+
+        if skip_until.is_none() {
+            // Stray '<' that don't start a tag must be escaped
+            result.push_str(&text.replace('<', "&lt;"));
+        }
+
+        let token = token.as_str();
+        let name = match captures.get(1) {
+            Some(name) => name.as_str().to_ascii_lowercase(),
+            None => {
+                // Comments are inert, and are used to carry Hugo shortcodes. Malformed comments are
+                // removed, as browsers end them early: "<!--><img onerror=...>-->" is not a comment.
+                if skip_until.is_none() {
+                    let body = &token[4..token.len() - 3];
+                    if body.starts_with('>') || body.starts_with("->") || body.contains("--") || body.ends_with('-') {
+                        warnings.push("Removed malformed comment".to_string());
+                    } else {
+                        result.push_str(token);
+                    }
+                }
+                continue;
+            }
+        };
+        let is_end_tag = token.starts_with("</");
+
+        if let Some(skipped) = &skip_until {
+            if is_end_tag && &name == skipped {
+                skip_until = None;
+            }
+            continue;
+        }
+
+        if !config.tags.contains(&name) {
+            if is_end_tag {
+                continue;
+            }
+            if RAW_TEXT_ELEMENTS.contains(&name.as_str()) {
+                warnings.push(format!("Removed <{}> element and its content", name));
+                skip_until = Some(name);
+            } else {
+                warnings.push(format!("Removed <{}> tag", name));
+            }
+            continue;
+        }
+
+        if is_end_tag {
+            result.push_str("</");
+            result.push_str(&name);
+            result.push('>');
+            continue;
+        }
+
+        result.push('<');
+        result.push_str(&name);
+        let attrs = captures.get(2).map_or("", |m| m.as_str());
+        for (attr, value) in parse_attributes(attrs) {
+            let attr = attr.to_ascii_lowercase();
+            if !is_allowed_attribute(config, &attr) {
+                warnings.push(format!("Removed '{}' attribute of <{}>", attr, name));
+                continue;
+            }
+            if URL_ATTRIBUTES.contains(&attr.as_str()) && !is_safe_url(config, value) {
+                warnings.push(format!("Removed '{}' attribute of <{}> with forbidden URL '{}'", attr, name, value));
+                continue;
+            }
+            result.push(' ');
+            result.push_str(&attr);
+            result.push_str("=\"");
+            result.push_str(&value.replace('"', "&quot;"));
+            result.push('"');
+        }
+        result.push('>');
+    }
+
+    if skip_until.is_none() {
+        result.push_str(&html[pos..].replace('<', "&lt;"));
+    }
+
+    result
+}
+
+/// Checks that a URL is relative or uses one of the allowed schemes.
+pub fn is_safe_url(config: &SanitizeConfig, url: &str) -> bool {
+    // Browsers ignore whitespace and control characters in URLs, e.g. "java\tscript:"
+    let url = url.chars()
+        .filter(|c| !c.is_ascii_whitespace() && !c.is_ascii_control())
+        .collect::<String>();
+
+    // Character references before the path, query or fragment are rejected, as browsers decode
+    // them in attribute values: "jav&#x61;script:" or "javascript&colon;" hide a scheme.
+    let path_start = url.find(['/', '?', '#']).unwrap_or(url.len());
+    if url[..path_start].contains('&') {
+        return false;
+    }
+
+    let scheme = match url.find(':') {
+        Some(pos) => &url[..pos],
+        None => return true,
+    };
+
+    if scheme.contains(['/', '?', '#']) {
+        // Relative URL with a colon in its path, query or fragment
+        true
+    } else {
+        config.url_schemes.contains(&scheme.to_ascii_lowercase())
+    }
+}
+
+fn is_allowed_attribute(config: &SanitizeConfig, attr: &str) -> bool {
+    config.attributes.iter().any(|allowed| {
+        if let Some(prefix) = allowed.strip_suffix('*') {
+            attr.starts_with(prefix)
+        } else {
+            attr == allowed
+        }
+    })
+}
+
+fn parse_attributes(attrs: &str) -> Vec<(&str, &str)> {
+    let attr_re = regex!(r#"([^\s"'>/=]+)(?:\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'=<>`]+)))?"#);
+
+    attr_re.captures_iter(attrs)
+        .map(|captures| {
+            let name = captures.get(1).unwrap().as_str();
+            let value = captures.get(2)
+                .or_else(|| captures.get(3))
+                .or_else(|| captures.get(4))
+                .map_or("", |m| m.as_str());
+            (name, value)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clean_html() {
+        let config = SanitizeConfig::default();
+        let mut warnings = Vec::new();
+
+        assert_eq!(
+            r#"<div class="row">"#,
+            clean_html(&config, "<div class='row'>", &mut warnings)
+        );
+        assert_eq!(
+            r#"<p>Hello </p><a href="https://example.com">x</a>"#,
+            clean_html(&config, r#"<p onclick="alert(1)">Hello <script>alert("</p>")</script></p><a href="https://example.com">x</a>"#, &mut warnings)
+        );
+        assert_eq!(
+            "<a>x</a>",
+            clean_html(&config, "<a href='jav&#x61;script:alert(1)'>x</a>", &mut warnings)
+        );
+        assert_eq!(
+            "<!-- {{< shortcode >}} -->",
+            clean_html(&config, "<!-- {{< shortcode >}} -->", &mut warnings)
+        );
+        assert_eq!(
+            "",
+            clean_html(&config, "<!--><img src=x onerror=alert(1)>-->", &mut warnings)
+        );
+        assert_eq!(
+            "",
+            clean_html(&config, "<!---><img src=x onerror=alert(1)>-->", &mut warnings)
+        );
+        assert_eq!(
+            "<a>x</a>",
+            clean_html(&config, "<a href='javascript&colon;alert(1)'>x</a>", &mut warnings)
+        );
+        assert_eq!(
+            "<div>Win!</div>",
+            clean_html(&config, r#"<div id="menu" style="position:fixed;inset:0">Win!</div>"#, &mut warnings)
+        );
+        assert_eq!(8, warnings.len());
+
+        assert!(is_safe_url(&config, "/posts/foo#bar"));
+        assert!(is_safe_url(&config, "/search?a=1&b=2"));
+        assert!(!is_safe_url(&config, "javascript&#58;alert(1)"));
+        assert!(is_safe_url(&config, "mailto:foo@example.com"));
+        assert!(!is_safe_url(&config, " java\tscript:alert(1)"));
+    }
+}
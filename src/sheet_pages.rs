@@ -0,0 +1,108 @@
+//! Content pages generated from the rows of a spreadsheet, for lists that are easier to maintain
+//! in a sheet than as one GDoc per page (events, team members, etc).
+//!
+//! A ToC row whose `gdoc_url` is a spreadsheet (and whose slug isn't a `/#data/` slug) is replaced
+//! by the rows of the spreadsheet's first tab (or of the first tab in `data_tabs`). These rows
+//! have the same columns as the ToC, and their slugs are relative to the ToC row's slug. The body
+//! of a page is the GDoc in its `gdoc_url` column, or the HTML of its `body` column, or the
+//! Markdown of its `body_markdown` column.
+
+use anyhow::{Context, Result};
+use crate::config::Config;
+use crate::data_sheets::DATA_SLUG_PREFIX;
+use crate::gdocs_site::{self, DocData, TocProblems};
+use crate::publish::HyperC;
+use crate::{sanitize, sheets};
+
+/// Is this ToC row a list of pages in a spreadsheet?
+pub fn is_sheet_pages(doc: &DocData) -> bool {
+    !doc.slug.starts_with(DATA_SLUG_PREFIX) &&
+        doc.gdoc_url.as_deref().is_some_and(|url| url.starts_with("https://docs.google.com/spreadsheets/"))
+}
+
+/// Replace the ToC rows that are lists of pages by the pages read from their spreadsheet.
+pub fn expand(docs: Vec<DocData>, gdocs_api: &google_docs1::Docs<HyperC>, config: &Config) -> Result<Vec<DocData>> {
+    if !docs.iter().any(is_sheet_pages) {
+        return Ok(docs);
+    }
+
+    let mut result = Vec::new();
+    for doc in docs {
+        if !is_sheet_pages(&doc) {
+            result.push(doc);
+            continue;
+        }
+
+        let sheet_id = doc.gdoc_url.as_deref().and_then(gdocs_site::get_doc_id).unwrap();
+        println!("Reading pages of '{}' from GSheet id={}", doc.slug, sheet_id);
+
+        let rows = sheets::read_rows(gdocs_api, sheet_id, doc.data_tabs.first().map(|s| s.as_str()))
+            .with_context(|| format!("Failed to read pages of '{}'", doc.slug))?;
+        let pages = DocData::read_sheet_rows(&rows, config)
+            .with_context(|| format!("Problem reading pages of '{}'", doc.slug))?;
+
+        for mut page in pages {
+            page.slug = format!("{}{}", doc.slug.trim_end_matches('/'), page.slug);
+            // Pages of an unpublished list are unpublished
+            page.publish &= doc.publish;
+            if page.author.is_none() {
+                page.author = doc.author.clone();
+            }
+            if page.categories.is_empty() {
+                page.categories = doc.categories.clone();
+            }
+            result.push(page);
+        }
+    }
+
+    // Check pages against each other and against the ToC
+    let problems = gdocs_site::check_docs(&result, config);
+    if !problems.is_empty() {
+        return Err(TocProblems(problems).into());
+    }
+
+    Ok(result)
+}
+
+/// Does this page have its body in the spreadsheet rather than in a GDoc?
+pub fn has_body(doc: &DocData) -> bool {
+    doc.body.is_some() || doc.body_markdown.is_some()
+}
+
+/// HTML of a page body read from a spreadsheet, sanitized unless the page is trusted.
+pub fn body_html(doc: &DocData, config: &Config, warnings: &mut Vec<String>) -> String {
+    let html = if let Some(markdown) = &doc.body_markdown {
+        let mut html = String::new();
+        pulldown_cmark::html::push_html(&mut html, pulldown_cmark::Parser::new(markdown));
+        html
+    } else {
+        doc.body.clone().unwrap_or_default()
+    };
+
+    if config.sanitize.enabled && !doc.trusted {
+        sanitize::clean_html(&config.sanitize, &html, warnings)
+    } else {
+        html
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_body_html() {
+        let csv = indoc::indoc! {"
+            title,slug,publish,body,body_markdown
+            Title,Slug,Publish,Body,Markdown
+            Tea,/tea,TRUE,,**Tea** time
+            Coffee,/coffee,TRUE,<p onclick='x()'>Coffee</p>,
+        "};
+        let config = serde_yaml::from_str("hugo_site_dir: site").unwrap();
+        let docs = crate::gdocs_site::DocData::read_csv(csv.as_bytes(), &config).unwrap();
+        let mut warnings = Vec::new();
+
+        assert!(super::has_body(&docs[0]));
+        assert_eq!("<p><strong>Tea</strong> time</p>\n", super::body_html(&docs[0], &config, &mut warnings));
+        assert_eq!("<p>Coffee</p>", super::body_html(&docs[1], &config, &mut warnings));
+        assert_eq!(1, warnings.len());
+    }
+}
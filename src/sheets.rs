@@ -0,0 +1,267 @@
+//! Reading spreadsheets with the Sheets API, which provides typed cell values, links and the
+//! spreadsheet's timezone, unlike CSV exports.
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{Duration, NaiveDate, TimeZone};
+use google_sheets4::api::{CellData, ExtendedValue};
+use crate::publish::HyperC;
+
+// Subset of the Sheets API response. Smart chips are not in the google-sheets4 data model yet.
+
+#[derive(Deserialize)]
+struct Spreadsheet {
+    properties: Option<SpreadsheetProperties>,
+    #[serde(default)]
+    sheets: Vec<Sheet>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SpreadsheetProperties {
+    time_zone: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Sheet {
+    properties: Option<SheetProperties>,
+    #[serde(default)]
+    data: Vec<GridData>,
+}
+
+#[derive(Deserialize)]
+struct SheetProperties {
+    title: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GridData {
+    #[serde(default)]
+    row_data: Vec<RowData>,
+}
+
+#[derive(Deserialize)]
+struct RowData {
+    #[serde(default)]
+    values: Vec<Cell>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Cell {
+    #[serde(flatten)]
+    data: CellData,
+    #[serde(default)]
+    chip_runs: Vec<ChipRun>,
+}
+
+#[derive(Deserialize)]
+struct ChipRun {
+    chip: Option<Chip>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Chip {
+    rich_link_properties: Option<RichLinkProperties>,
+}
+
+#[derive(Deserialize)]
+struct RichLinkProperties {
+    uri: Option<String>,
+}
+
+/// A spreadsheet tab and its rows
+pub struct Tab {
+    pub title: String,
+    /// Cell values, converted as described in `read_tabs`
+    pub rows: Vec<csv::StringRecord>,
+    /// Cell text as displayed in the spreadsheet (e.g. "2.50 €", "15%"), with the same shape as
+    /// `rows`. Link cells have the same text as in `rows`.
+    pub formatted: Vec<csv::StringRecord>,
+}
+
+/// Read the rows of a spreadsheet tab (the first one if `tab` is `None`) as CSV records, so that
+/// they can be processed like a CSV export. See `read_tabs` for details.
+pub fn read_rows(gdocs_api: &google_docs1::Docs<HyperC>, spreadsheet_id: &str, tab: Option<&str>) -> Result<Vec<csv::StringRecord>> {
+    let tabs = tab.map(|tab| vec![tab.to_string()]).unwrap_or_default();
+    let tab = read_tabs(gdocs_api, spreadsheet_id, &tabs)?.into_iter().next();
+    Ok(tab.map(|tab| tab.rows).unwrap_or_default())
+}
+
+/// Read the rows of some tabs of a spreadsheet (all of them if `tabs` is empty) as CSV records.
+/// The position of each record is its sheet row number.
+///
+/// Cell values are converted to strings:
+/// - booleans are `TRUE` or `FALSE`,
+/// - dates are in RFC 3339 format, in the spreadsheet's timezone,
+/// - numbers are not formatted,
+/// - cells containing a smart chip, and cells with a hyperlink in a column whose name ends with
+///   `_url`, are replaced by the link URL.
+///
+pub fn read_tabs(gdocs_api: &google_docs1::Docs<HyperC>, spreadsheet_id: &str, tabs: &[String]) -> Result<Vec<Tab>> {
+
+    let mut url = format!(
+        "https://sheets.googleapis.com/v4/spreadsheets/{}?includeGridData=true&fields={}",
+        spreadsheet_id,
+        "properties.timeZone,sheets.properties.title,sheets.data.rowData.values(effectiveValue,effectiveFormat.numberFormat,formattedValue,hyperlink,chipRuns)"
+    );
+    for tab in tabs {
+        // Quote the tab name so that it isn't mistaken for a range
+        let range = format!("'{}'", tab.replace('\'', "''"));
+        url.push_str("&ranges=");
+        url.extend(url::form_urlencoded::byte_serialize(range.as_bytes()));
+    }
+
+    // Sheets API requests are allowed with the Drive read-only scope
+    let (_, bytes) = tokio::runtime::Handle::current()
+        .block_on(crate::publish::download_url(gdocs_api, &url))
+        .with_context(|| format!("Failed to read spreadsheet {}", spreadsheet_id))?;
+
+    let spreadsheet: Spreadsheet = serde_json::from_slice(&bytes)
+        .with_context(|| format!("Failed to parse spreadsheet {}", spreadsheet_id))?;
+
+    let time_zone: chrono_tz::Tz = match spreadsheet.properties.and_then(|p| p.time_zone) {
+        Some(tz) => tz.parse().map_err(|e| anyhow!("Invalid spreadsheet timezone: {}", e))?,
+        None => chrono_tz::UTC,
+    };
+
+    let tabs = spreadsheet.sheets.into_iter()
+        .map(|sheet| {
+            let (rows, formatted) = sheet.data.into_iter()
+                .next()
+                .map(|data| grid_records(data.row_data, &time_zone))
+                .unwrap_or_default();
+            Tab {
+                title: sheet.properties.and_then(|p| p.title).unwrap_or_default(),
+                rows,
+                formatted,
+            }
+        })
+        .collect();
+
+    Ok(tabs)
+}
+
+/// Records of the cell values and of the displayed cell text.
+fn grid_records(rows: Vec<RowData>, time_zone: &chrono_tz::Tz) -> (Vec<csv::StringRecord>, Vec<csv::StringRecord>) {
+    let mut headers = Vec::new();
+    let mut records = Vec::new();
+    let mut formatted = Vec::new();
+
+    for (row_idx, row) in rows.into_iter().enumerate() {
+        let mut record = csv::StringRecord::new();
+        let mut formatted_record = csv::StringRecord::new();
+        for (col_idx, cell) in row.values.into_iter().enumerate() {
+            let is_url_column = headers.get(col_idx).is_some_and(|h: &String| h.ends_with("_url"));
+            let (value, text) = cell_text(cell, time_zone, is_url_column);
+            record.push_field(&value);
+            formatted_record.push_field(&text);
+        }
+
+        if row_idx == 0 {
+            headers = record.iter().map(|s| s.to_string()).collect();
+        }
+
+        // Rows are 1-based in spreadsheets
+        let mut position = csv::Position::new();
+        position.set_line(row_idx as u64 + 1).set_record(row_idx as u64);
+        record.set_position(Some(position.clone()));
+        formatted_record.set_position(Some(position));
+
+        records.push(record);
+        formatted.push(formatted_record);
+    }
+
+    // Trailing empty cells are not returned by the API
+    let width = headers.len();
+    for record in records.iter_mut().chain(formatted.iter_mut()) {
+        while record.len() < width {
+            record.push_field("");
+        }
+    }
+
+    (records, formatted)
+}
+
+/// The value of a cell, and its displayed text.
+fn cell_text(cell: Cell, time_zone: &chrono_tz::Tz, is_url_column: bool) -> (String, String) {
+    let chip_uri = cell.chip_runs.into_iter()
+        .filter_map(|run| run.chip?.rich_link_properties?.uri)
+        .next();
+    if let Some(uri) = chip_uri {
+        return (uri.clone(), uri);
+    }
+
+    let data = cell.data;
+    if is_url_column {
+        if let Some(link) = data.hyperlink {
+            return (link.clone(), link);
+        }
+    }
+
+    let formatted = data.formatted_value.unwrap_or_default();
+
+    let is_date = data.effective_format
+        .and_then(|f| f.number_format)
+        .and_then(|f| f.type_)
+        .is_some_and(|t| t == "DATE" || t == "DATE_TIME");
+
+    let value = match data.effective_value {
+        Some(ExtendedValue { bool_value: Some(b), .. }) => {
+            if b { "TRUE" } else { "FALSE" }.to_string()
+        },
+        Some(ExtendedValue { number_value: Some(n), .. }) if is_date => {
+            serial_to_date(n, time_zone).unwrap_or_else(|| formatted.clone())
+        },
+        Some(ExtendedValue { number_value: Some(n), .. }) => {
+            if n.fract() == 0.0 { format!("{}", n as i64) } else { n.to_string() }
+        },
+        Some(ExtendedValue { string_value: Some(s), .. }) => s,
+        _ => formatted.clone(),
+    };
+
+    (value, formatted)
+}
+
+/// Converts a spreadsheet date, a number of days since 1899-12-30 in the spreadsheet's timezone,
+/// to RFC 3339.
+fn serial_to_date(serial: f64, time_zone: &chrono_tz::Tz) -> Option<String> {
+    let epoch = NaiveDate::from_ymd_opt(1899, 12, 30)?.and_hms_opt(0, 0, 0)?;
+    let local = epoch + Duration::milliseconds((serial * 86_400_000.0).round() as i64);
+    let date = time_zone.from_local_datetime(&local).earliest()?;
+    Some(date.to_rfc3339())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_serial_to_date() {
+        let tz: chrono_tz::Tz = "Europe/Paris".parse().unwrap();
+        assert_eq!(Some("2024-03-01T12:00:00+01:00".to_string()), super::serial_to_date(45352.5, &tz));
+    }
+
+    #[test]
+    fn test_cell_text() {
+        let tz = chrono_tz::UTC;
+        let cell = |json| serde_json::from_value::<super::Cell>(json).unwrap();
+
+        assert_eq!(("2.5".to_string(), "2.50 €".to_string()), super::cell_text(cell(serde_json::json!({
+            "effectiveValue": { "numberValue": 2.5 },
+            "formattedValue": "2.50 €",
+        })), &tz, false));
+
+        assert_eq!(("2024-03-01T00:00:00+00:00".to_string(), "01/03/2024".to_string()), super::cell_text(cell(serde_json::json!({
+            "effectiveValue": { "numberValue": 45352 },
+            "effectiveFormat": { "numberFormat": { "type": "DATE" } },
+            "formattedValue": "01/03/2024",
+        })), &tz, false));
+
+        // Links replace both the value and the text
+        assert_eq!(("https://example.com/".to_string(), "https://example.com/".to_string()), super::cell_text(cell(serde_json::json!({
+            "effectiveValue": { "stringValue": "Example" },
+            "formattedValue": "Example",
+            "hyperlink": "https://example.com/",
+        })), &tz, true));
+    }
+}
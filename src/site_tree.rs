@@ -0,0 +1,212 @@
+//! Hierarchy of the site pages, from their slugs.
+//!
+//! The parent of a page is the closest page whose slug is a prefix path of the page's slug, e.g.
+//! `/about` for `/about/team/john` if there's no `/about/team` page. The home page `/` is the
+//! parent of top-level pages. Pages written as single files by the layout rules (by default,
+//! category posts) and data rows are not in the tree.
+
+use std::collections::BTreeMap;
+use crate::config::{Bundle, LayoutRule};
+use crate::data_sheets::DATA_SLUG_PREFIX;
+use crate::gdocs_site::DocData;
+use crate::layout;
+
+#[derive(Debug)]
+struct Node {
+    title: String,
+    weight: Option<i16>,
+    /// Is the page on the site? Navigation links only point to live pages.
+    live: bool,
+    parent: Option<String>,
+    children: Vec<String>,
+}
+
+/// A link to a page, for navigation front matter.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PageLink {
+    pub title: String,
+    pub url: String,
+}
+
+#[derive(Debug, Default)]
+pub struct SiteTree {
+    nodes: BTreeMap<String, Node>,
+}
+
+impl SiteTree {
+    pub fn new<'a>(docs: impl IntoIterator<Item = &'a DocData>, layout: &[LayoutRule], live: impl Fn(&DocData) -> bool) -> SiteTree {
+        let mut nodes = docs.into_iter()
+            .filter(|doc| !doc.slug.starts_with(DATA_SLUG_PREFIX))
+            .filter(|doc| layout::locate(doc, layout).bundle != Bundle::File)
+            .map(|doc| (doc.slug.clone(), Node {
+                title: doc.title.clone(),
+                weight: doc.weight,
+                live: live(doc),
+                parent: None,
+                children: Vec::new(),
+            }))
+            .collect::<BTreeMap<_, _>>();
+
+        let parents = nodes.keys()
+            .filter_map(|slug| {
+                let parent = ancestors(slug).find(|ancestor| nodes.contains_key(*ancestor))?;
+                Some((slug.clone(), parent.to_string()))
+            })
+            .collect::<Vec<_>>();
+
+        for (slug, parent) in parents {
+            nodes.get_mut(&parent).unwrap().children.push(slug.clone());
+            nodes.get_mut(&slug).unwrap().parent = Some(parent);
+        }
+
+        // Order children by weight (pages without a weight last), then title
+        let order = nodes.iter()
+            .map(|(slug, node)| (slug.clone(), (node.weight.is_none(), node.weight, node.title.clone())))
+            .collect::<BTreeMap<_, _>>();
+        for node in nodes.values_mut() {
+            node.children.sort_by_key(|child| &order[child]);
+        }
+
+        SiteTree { nodes }
+    }
+
+    pub fn parent(&self, slug: &str) -> Option<&str> {
+        self.nodes.get(slug)?.parent.as_deref()
+    }
+
+    /// Children of a page, ordered by weight.
+    pub fn children(&self, slug: &str) -> &[String] {
+        self.nodes.get(slug).map_or(&[], |node| node.children.as_slice())
+    }
+
+    /// Children of the page's parent, including the page itself.
+    pub fn siblings(&self, slug: &str) -> &[String] {
+        match self.parent(slug) {
+            Some(parent) => self.children(parent),
+            None => &[],
+        }
+    }
+
+    pub fn has_children(&self, slug: &str) -> bool {
+        !self.children(slug).is_empty()
+    }
+
+    /// Ancestors of a page, closest first, ending with the home page if there's one. Pages that are
+    /// not in the tree (e.g. posts) are placed below the closest page of their slug path.
+    pub fn ancestors<'a>(&'a self, slug: &'a str) -> impl Iterator<Item = &'a str> {
+        let first = match self.nodes.get(slug) {
+            Some(node) => node.parent.as_deref(),
+            None => ancestors(slug).find(|ancestor| self.nodes.contains_key(*ancestor)),
+        };
+        std::iter::successors(first, move |slug| self.parent(slug))
+    }
+
+    /// Links to the live ancestors of a page, from the root.
+    pub fn breadcrumbs(&self, slug: &str) -> Vec<PageLink> {
+        let mut result = Vec::new();
+        let mut current = self.parent(slug);
+        while let Some(slug) = current {
+            result.extend(self.link(slug));
+            current = self.parent(slug);
+        }
+        result.reverse();
+        result
+    }
+
+    /// Links to the previous and next live siblings of a page.
+    pub fn prev_next(&self, slug: &str) -> (Option<PageLink>, Option<PageLink>) {
+        let siblings = self.siblings(slug);
+        let Some(pos) = siblings.iter().position(|s| s == slug) else {
+            return (None, None);
+        };
+        let prev = siblings[..pos].iter().rev().find_map(|s| self.link(s));
+        let next = siblings[pos + 1..].iter().find_map(|s| self.link(s));
+        (prev, next)
+    }
+
+    /// Links to the live children of a page.
+    pub fn child_links(&self, slug: &str) -> Vec<PageLink> {
+        self.children(slug).iter().filter_map(|s| self.link(s)).collect()
+    }
+
+    /// Pages whose parent path isn't a page, e.g. `/a/b` if there's no `/a` page.
+    pub fn orphans(&self) -> Vec<&str> {
+        self.nodes.keys()
+            .filter(|slug| ancestors(slug).next().is_some_and(|parent| parent != "/" && !self.nodes.contains_key(parent)))
+            .map(|slug| slug.as_str())
+            .collect()
+    }
+
+    fn link(&self, slug: &str) -> Option<PageLink> {
+        let node = self.nodes.get(slug).filter(|node| node.live)?;
+        Some(PageLink {
+            title: node.title.clone(),
+            url: format!("{}/", slug.trim_end_matches('/')),
+        })
+    }
+}
+
+/// Ancestors of a slug, closest first, ending with the root `/`.
+fn ancestors(slug: &str) -> impl Iterator<Item = &str> {
+    let mut current = slug;
+    std::iter::from_fn(move || {
+        if current == "/" {
+            return None;
+        }
+        current = match current.rfind('/') {
+            Some(0) | None => "/",
+            Some(pos) => &current[..pos],
+        };
+        Some(current)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PageLink;
+
+    #[test]
+    fn test_site_tree() {
+        let csv = indoc::indoc! {"
+            title,slug,weight,category,publish,body
+            Title,Slug,Weight,Category,Publish,Body
+            Home,/,,,TRUE,x
+            About,/about,2,,TRUE,x
+            About us,/about-us,1,,TRUE,x
+            Team,/about/team,2,,TRUE,x
+            History,/about/history,1,,TRUE,x
+            Draft,/about/draft,3,,FALSE,x
+            John,/about/team/john,,,TRUE,x
+            Orphan,/people/jane,,,TRUE,x
+            Launch,/about/launch,,News,TRUE,x
+        "};
+        let config: crate::config::Config = serde_yaml::from_str("hugo_site_dir: site").unwrap();
+        let docs = crate::gdocs_site::DocData::read_csv(csv.as_bytes(), &config).unwrap();
+        let tree = super::SiteTree::new(&docs, &config.layout, |doc| doc.publish);
+
+        let link = |title: &str, url: &str| PageLink { title: title.to_string(), url: url.to_string() };
+
+        assert_eq!(["/about-us", "/about", "/people/jane"], tree.children("/"));
+        assert_eq!(["/about/history", "/about/team", "/about/draft"], tree.children("/about"));
+        assert!(!tree.has_children("/about-us"));
+        assert_eq!(Some("/about/team"), tree.parent("/about/team/john"));
+        assert_eq!(Some("/"), tree.parent("/people/jane"));
+
+        assert_eq!(vec![link("Home", "/"), link("About", "/about/"), link("Team", "/about/team/")], tree.breadcrumbs("/about/team/john"));
+        assert_eq!((Some(link("History", "/about/history/")), None), tree.prev_next("/about/team"));
+        assert_eq!(vec!["/people/jane"], tree.orphans());
+        assert_eq!(vec!["/about/team", "/about", "/"], tree.ancestors("/about/team/john").collect::<Vec<_>>());
+        // Posts are not in the tree
+        assert_eq!(vec!["/about", "/"], tree.ancestors("/about/launch").collect::<Vec<_>>());
+
+        // Categorized pages are in the tree if the layout doesn't write them as single files
+        let config: crate::config::Config = serde_yaml::from_str(indoc::indoc! {"
+            hugo_site_dir: site
+            layout:
+              - category: news
+                dir: news
+        "}).unwrap();
+        let tree = super::SiteTree::new(&docs, &config.layout, |doc| doc.publish);
+        assert_eq!(["/about/history", "/about/team", "/about/draft", "/about/launch"], tree.children("/about"));
+    }
+}
@@ -0,0 +1,123 @@
+//! History of the slugs of each GDoc, to redirect old URLs with Hugo aliases when a slug changes.
+//!
+//! The history is stored in `slug-history.yml` in the download directory, and should be kept
+//! between runs (e.g. committed along with the site).
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result};
+use crate::gdocs_site::{self, DocData};
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct DocHistory {
+    slug: String,
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    previous: BTreeSet<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct SlugHistory {
+    path: PathBuf,
+    /// History by doc id
+    docs: BTreeMap<String, DocHistory>,
+}
+
+impl SlugHistory {
+    pub fn load(download_dir: &Path) -> Result<SlugHistory> {
+        let path = download_dir.join("slug-history.yml");
+        let docs = if path.exists() {
+            let file = fs::File::open(&path)?;
+            serde_yaml::from_reader(file).with_context(|| format!("Failed to read {:?}", path))?
+        } else {
+            BTreeMap::new()
+        };
+        Ok(SlugHistory { path, docs })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        fs::create_dir_all(self.path.parent().unwrap())?;
+        serde_yaml::to_writer(fs::File::create(&self.path)?, &self.docs)
+            .with_context(|| format!("Cannot write to {:?}", self.path))
+    }
+
+    /// Record the current slugs of docs, and compute the aliases of each page (previous slugs and
+    /// the `aliases` column), by page slug.
+    ///
+    /// Previous slugs that are now used by another page are not aliases. Aliases claimed by several
+    /// pages are ignored with a warning.
+    pub fn update(&mut self, docs: &[DocData]) -> HashMap<String, Vec<String>> {
+        let live_slugs = docs.iter().map(|doc| doc.slug.as_str()).collect::<HashSet<_>>();
+
+        let mut claims = BTreeMap::<String, Vec<&str>>::new();
+
+        for doc in docs {
+            let mut aliases = doc.aliases.iter().cloned().collect::<BTreeSet<_>>();
+
+            if let Some(id) = doc.gdoc_url.as_deref().and_then(gdocs_site::get_doc_id) {
+                let history = self.docs.entry(id.to_string()).or_insert_with(|| DocHistory {
+                    slug: doc.slug.clone(),
+                    previous: BTreeSet::new(),
+                });
+                if history.slug != doc.slug {
+                    println!("Slug of '{}' changed, was '{}'", doc.slug, history.slug);
+                    let old_slug = std::mem::replace(&mut history.slug, doc.slug.clone());
+                    history.previous.insert(old_slug);
+                }
+                history.previous.remove(&doc.slug);
+                aliases.extend(history.previous.iter().cloned());
+            }
+
+            for alias in aliases {
+                if !live_slugs.contains(alias.as_str()) {
+                    claims.entry(alias).or_default().push(&doc.slug);
+                }
+            }
+        }
+
+        let mut result = HashMap::<String, Vec<String>>::new();
+        for (alias, pages) in claims {
+            if let [page] = pages.as_slice() {
+                result.entry(page.to_string()).or_default().push(format!("{}/", alias));
+            } else {
+                println!("Warning: alias '{}' ignored, as it's claimed by several pages: {}", alias, pages.join(", "));
+            }
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_aliases() {
+        let toc = |rows: &str| {
+            let csv = format!("title,slug,aliases,publish,gdoc_url\nTitle,Slug,Aliases,Publish,GDoc\n{}", rows);
+            let config = serde_yaml::from_str("hugo_site_dir: site").unwrap();
+            crate::gdocs_site::DocData::read_csv(csv.as_bytes(), &config).unwrap()
+        };
+
+        let mut history = super::SlugHistory::default();
+        history.update(&toc(indoc::indoc! {"
+            A,/a,,TRUE,https://docs.google.com/document/d/a/edit
+            B,/b,,TRUE,https://docs.google.com/document/d/b/edit
+        "}));
+
+        // 'a' is renamed, and 'b' takes its old slug
+        let aliases = history.update(&toc(indoc::indoc! {"
+            A,/new-a,/old,TRUE,https://docs.google.com/document/d/a/edit
+            B,/a,,TRUE,https://docs.google.com/document/d/b/edit
+        "}));
+        assert_eq!(Some(&vec!["/old/".to_string()]), aliases.get("/new-a"));
+        assert_eq!(Some(&vec!["/b/".to_string()]), aliases.get("/a"));
+
+        // 'a' gets back its original slug
+        let aliases = history.update(&toc(indoc::indoc! {"
+            A,/a,,TRUE,https://docs.google.com/document/d/a/edit
+            B,/b,,TRUE,https://docs.google.com/document/d/b/edit
+        "}));
+        assert_eq!(Some(&vec!["/new-a/".to_string()]), aliases.get("/a"));
+        assert_eq!(None, aliases.get("/b"));
+    }
+}
@@ -0,0 +1,94 @@
+//! Conversion of inline styles to CSS classes.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use crate::config::StylingConfig;
+
+/// CSS rules collected while rendering documents, keyed by class name.
+///
+/// Class names are derived from a hash of the style declarations, so that a given combination
+/// of styles always gets the same class name, whatever the page it's found in. This allows
+/// merging the rules of all pages into a site-wide stylesheet.
+///
+#[derive(Debug, Default)]
+pub struct StyleSheet {
+    rules: BTreeMap<String, String>,
+}
+
+impl StyleSheet {
+    /// Get the class name for some style declarations (e.g. `color:red;font-variant:small-caps;`)
+    /// and record the corresponding rule.
+    pub fn class_for(&mut self, config: &StylingConfig, declarations: &str) -> String {
+        let class = format!("{}{:08x}", config.class_prefix, stable_hash(declarations) as u32);
+        self.rules.entry(class.clone()).or_insert_with(|| declarations.to_string());
+        class
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn merge(&mut self, other: StyleSheet) {
+        self.rules.extend(other.rules);
+    }
+
+    pub fn to_css(&self) -> String {
+        let mut css = String::new();
+        for (class, declarations) in &self.rules {
+            // Writing to a string never fails
+            writeln!(css, ".{} {{ {} }}", class, declarations).unwrap();
+        }
+        css
+    }
+}
+
+/// Finds the semantic name of a color in the palette. Palette keys are `#rrggbb` hex colors.
+pub fn palette_name(config: &StylingConfig, red: f32, green: f32, blue: f32) -> Option<&str> {
+    if config.palette.is_empty() {
+        return None;
+    }
+
+    let hex = format!(
+        "{:02x}{:02x}{:02x}",
+        (red * 255.0).round() as u8,
+        (green * 255.0).round() as u8,
+        (blue * 255.0).round() as u8
+    );
+
+    config.palette.iter()
+        .find(|(color, _)| color.trim_start_matches('#').eq_ignore_ascii_case(&hex))
+        .map(|(_, name)| name.as_str())
+}
+
+/// A hash that, unlike `DefaultHasher`, is guaranteed to be stable across Rust releases
+/// (64-bit FNV-1a).
+pub fn stable_hash(s: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in s.bytes() {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_class_names() {
+        let config = StylingConfig {
+            palette: maplit::btreemap! { "#1A73E8".to_string() => "brand".to_string() },
+            ..StylingConfig::default()
+        };
+
+        let mut sheet = StyleSheet::default();
+        let class = sheet.class_for(&config, "font-variant: small-caps;");
+        assert_eq!(class, sheet.class_for(&config, "font-variant: small-caps;"));
+        assert!(class.starts_with("gd-"));
+        assert_eq!(format!(".{} {{ font-variant: small-caps; }}\n", class), sheet.to_css());
+
+        assert_eq!(Some("brand"), palette_name(&config, 0.1019, 0.4509, 0.9098));
+        assert_eq!(None, palette_name(&config, 0.0, 0.0, 0.0));
+    }
+}
@@ -0,0 +1,74 @@
+//! Suggestions (proposed edits) in GDocs.
+//!
+//! The Docs API returns suggestions according to the requested view mode. They're only visible in
+//! `SUGGESTIONS_INLINE` mode, as `suggested*` fields that contain or are keyed by suggestion ids.
+
+use std::collections::BTreeMap;
+use serde_json::Value;
+
+/// Open suggestions of a doc fetched in `SUGGESTIONS_INLINE` mode, as suggestion id -> changes.
+pub fn open_suggestions(doc: &google_docs1::api::Document) -> BTreeMap<String, Vec<String>> {
+    let mut result = BTreeMap::new();
+    if let Ok(value) = serde_json::to_value(doc) {
+        collect(&value, &mut result);
+    }
+    result
+}
+
+fn collect(value: &Value, result: &mut BTreeMap<String, Vec<String>>) {
+    match value {
+        Value::Object(map) => {
+            let content = map.get("content").and_then(Value::as_str).map(|text| text.trim());
+
+            for (key, value) in map {
+                let change = match key.as_str() {
+                    "suggestedInsertionIds" => format!("insert '{}'", content.unwrap_or_default()),
+                    "suggestedDeletionIds" => format!("delete '{}'", content.unwrap_or_default()),
+                    key if key.starts_with("suggested") => "style change".to_string(),
+                    _ => {
+                        collect(value, result);
+                        continue;
+                    }
+                };
+
+                // Lists of ids, or changes keyed by id
+                let ids = match value {
+                    Value::Array(ids) => ids.iter().filter_map(Value::as_str).collect::<Vec<_>>(),
+                    Value::Object(changes) => changes.keys().map(|id| id.as_str()).collect(),
+                    _ => Vec::new(),
+                };
+                for id in ids {
+                    let changes = result.entry(id.to_string()).or_default();
+                    if !changes.contains(&change) {
+                        changes.push(change.clone());
+                    }
+                }
+            }
+        },
+        Value::Array(values) => values.iter().for_each(|value| collect(value, result)),
+        _ => {},
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_open_suggestions() {
+        let doc: google_docs1::api::Document = serde_json::from_value(serde_json::json!({
+            "body": { "content": [
+                { "paragraph": { "elements": [
+                    { "textRun": { "content": "Hello " } },
+                    { "textRun": { "content": "big ", "suggestedDeletionIds": ["suggest.1"] } },
+                    { "textRun": { "content": "world\n", "suggestedInsertionIds": ["suggest.2"],
+                        "suggestedTextStyleChanges": { "suggest.3": { "textStyle": { "bold": true } } } } },
+                ]}},
+            ]},
+        })).unwrap();
+
+        let suggestions = super::open_suggestions(&doc);
+        assert_eq!(3, suggestions.len());
+        assert_eq!(vec!["delete 'big'"], suggestions["suggest.1"]);
+        assert_eq!(vec!["insert 'world'"], suggestions["suggest.2"]);
+        assert_eq!(vec!["style change"], suggestions["suggest.3"]);
+    }
+}
@@ -1,5 +1,6 @@
-use std::collections::hash_map::DefaultHasher;
-use std::hash::Hasher;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use crate::config::{BrokenLinkPolicy, LinkRule, LinksConfig, SummaryConfig, SummaryStrategy, PER_DOMAIN_TARGET};
 use crate::hugo_site::FrontMatter;
 use scraper::Selector;
 use scraper::ElementRef;
@@ -10,7 +11,8 @@ use itertools::Itertools;
 use rayon::prelude::*;
 use tendril::StrTendril;
 use crate::gdoc_to_html::ImageReference;
-use crate::SiteData;
+use crate::styles::stable_hash;
+use crate::{gdocs_site, BrokenLink, SiteData, UnknownDocLink};
 
 // Builds a `html5ever::QualName` with no prefix nor namespace
 macro_rules! qname {
@@ -27,49 +29,118 @@ pub fn remove_head(dom: &mut scraper::Html) {
     }
 }
 
-pub fn rewrite_links(dom: &mut scraper::Html, site_data: &SiteData) -> Result<()> {
+/// Rewrite links to GDocs and add attributes to external links.
+///
+/// Links to GDocs that are not in the site are returned, and are handled according to the
+/// broken links policy. `lang` is the language of the page, used to link to translations.
+pub fn rewrite_links(dom: &mut scraper::Html, site_data: &SiteData, links: &LinksConfig, page: &str, lang: Option<&str>) -> Result<Vec<BrokenLink>> {
     let selector = scraper::Selector::parse("a").unwrap();
-    let ids = dom.select(&selector).map(|elt| elt.id()).collect::<Vec<_>>();
+    let ids_and_text = dom.select(&selector)
+        .map(|elt| (elt.id(), elt.text().join("")))
+        .collect::<Vec<_>>();
 
-    for id in ids {
+    let mut broken_links = Vec::new();
+
+    for (id, text) in ids_and_text {
         let mut node = dom.tree.get_mut(id).unwrap();
+        let mut icon = None;
         if let scraper::Node::Element(elt) = node.value() {
             if let Some(href) = elt.attrs.get_mut(&qname!("href")) {
-                if let Some(new_href) = site_data.rewrite_href(href)? {
-                    *href = new_href.into();
+                match site_data.rewrite_href(href, lang) {
+                    Ok(Some(new_href)) => *href = new_href.into(),
+                    Ok(None) => (),
+                    Err(err) => {
+                        let target = err.downcast::<UnknownDocLink>()?;
+                        println!("Warning: {} - {} (link text: '{}')", page, target, text);
+                        broken_links.push(BrokenLink { page: page.to_string(), text, target });
+                        if links.broken == BrokenLinkPolicy::WarnUnlink {
+                            elt.attrs.remove(&qname!("href"));
+                        }
+                        continue;
+                    }
                 }
 
                 if href.starts_with("https://") || href.starts_with("http://") {
-                    use std::hash::Hash;
                     let url = reqwest::Url::parse(href)?;
-                    match url.host().unwrap() {
-                        url::Host::Domain(s) => {
-                            let mut hasher = DefaultHasher::new();
-                            s.hash(&mut hasher);
-                            let h = hasher.finish();
-                            let target = format!("{:X}", h);
-                            elt.attrs.insert(qname!("target"), target.into());
-                        },
-                        _ => (),
+                    if let Some(url::Host::Domain(domain)) = url.host() {
+                        if let Some(rule) = external_link_rule(links, domain) {
+                            if let Some(target) = &rule.target {
+                                let target = if target == PER_DOMAIN_TARGET {
+                                    format!("{:X}", stable_hash(domain))
+                                } else {
+                                    target.clone()
+                                };
+                                elt.attrs.insert(qname!("target"), target.into());
+                            }
+                            if let Some(rel) = &rule.rel {
+                                elt.attrs.insert(qname!("rel"), rel.as_str().into());
+                            }
+                            if let Some(class) = &rule.class {
+                                let class = match elt.attrs.get(&qname!("class")) {
+                                    Some(existing) => format!("{} {}", existing, class),
+                                    None => class.clone(),
+                                };
+                                elt.attrs.insert(qname!("class"), class.into());
+                            }
+                            icon = rule.icon.as_ref();
+                        }
                     }
                 }
             }
         }
+
+        if let Some(icon) = icon {
+            let span = scraper::node::Element::new(qname!("span"), vec![
+                html5ever::Attribute { name: qname!("class"), value: icon.as_str().into() },
+                html5ever::Attribute { name: qname!("aria-hidden"), value: "true".into() },
+            ]);
+            node.append(scraper::Node::Element(span));
+        }
     }
 
-    Ok(())
+    Ok(broken_links)
+}
+
+/// Find the rule to apply to an external link. Returns `None` for internal domains.
+fn external_link_rule<'a>(links: &'a LinksConfig, domain: &str) -> Option<&'a LinkRule> {
+    if links.internal_domains.iter().any(|pattern| domain_matches(pattern, domain)) {
+        return None;
+    }
+
+    let rule = links.rules.iter()
+        .find(|rule| rule.domains.iter().any(|pattern| domain_matches(pattern, domain)))
+        .unwrap_or(&links.default);
+
+    Some(rule)
+}
+
+/// Checks if a domain matches a pattern: either a domain name, or `*.` followed by a domain
+/// name to also match all its subdomains.
+fn domain_matches(pattern: &str, domain: &str) -> bool {
+    if let Some(parent) = pattern.strip_prefix("*.") {
+        domain.eq_ignore_ascii_case(parent) ||
+            (domain.len() > parent.len() &&
+                domain[domain.len() - parent.len()..].eq_ignore_ascii_case(parent) &&
+                domain.as_bytes()[domain.len() - parent.len() - 1] == b'.')
+    } else {
+        domain.eq_ignore_ascii_case(pattern)
+    }
 }
 
 /// Extract title, banner and summary:
-/// - the content before <h1> becomes the front matter's description and is removed
-/// - the first image found in the description becomes the front matter's banner
+/// - the content before <h1> is removed, and becomes the front matter's summary and description
+///   with the `before_h1` strategies. Other strategies are applied by [`extract_summary`].
+/// - the first image found in the content before <h1> becomes the front matter's banner
 /// - the <h1> tag is removes, as it's inserted by the page template from the front matter's title
 ///
 /// NOTE: image URLs must have been resolved so that the banner URL is correct.
-pub fn extract_title_and_summary(doc: &mut scraper::Html, fm: &mut FrontMatter) -> Result<()> {
+pub fn extract_title_and_summary(doc: &mut scraper::Html, fm: &mut FrontMatter, config: &SummaryConfig) -> Result<()> {
     let h1_selector = Selector::parse("h1").unwrap();
     let img_selector = Selector::parse("img").unwrap();
     let mut ids = Vec::new();
+    let mut summary = String::new();
+    let mut summary_text = String::new();
+    let mut summary_html = String::new();
 
     if let Some(h1) = doc.select(&h1_selector).next() {
         let txt = h1.text().join(" ");
@@ -77,11 +148,12 @@ pub fn extract_title_and_summary(doc: &mut scraper::Html, fm: &mut FrontMatter)
         fm.title = txt;
 
         // Summary is all the text preceding <h1>
-        let mut summary = String::new();
         for sibling in h1.prev_siblings().collect::<Vec<_>>().into_iter().rev() { // prev_siblings iterates in reverse order
             if let Some(elt) = ElementRef::wrap(sibling) {
                 summary += &elt.text().join(" ");
                 summary += " ";
+                summary_text += &display_text(elt);
+                summary_html += &elt.html();
 
                 // Img above <h1> becomes the article banner
                 if let Some(img) = elt.select(&img_selector).next() {
@@ -96,19 +168,108 @@ pub fn extract_title_and_summary(doc: &mut scraper::Html, fm: &mut FrontMatter)
             }
             ids.push(sibling.id());
         }
-        if !summary.is_empty() {
-            fm.description = Some(summary.clone());
-            fm.summary = Some(summary);
-        }
     }
 
     for id in ids {
         doc.tree.get_mut(id).unwrap().detach();
     }
 
+    match config.strategy {
+        SummaryStrategy::BeforeH1 => if !summary.is_empty() {
+            fm.description = Some(summary.clone());
+            fm.summary = Some(summary);
+        },
+        SummaryStrategy::BeforeH1Html => if !summary.trim().is_empty() {
+            fm.description = Some(description(&summary_text, config.description_length));
+            fm.summary = Some(summary_html);
+        },
+        _ => extract_summary(doc, fm, config),
+    }
+
     Ok(())
 }
 
+/// Set the front matter's summary and description with the strategies that don't use the content
+/// before <h1>. With the `column` strategy, the summary is the one already in the front matter.
+pub fn extract_summary(doc: &mut scraper::Html, fm: &mut FrontMatter, config: &SummaryConfig) {
+    match config.strategy {
+        SummaryStrategy::BeforeH1 | SummaryStrategy::BeforeH1Html | SummaryStrategy::Column => {},
+        SummaryStrategy::Marker => {
+            let selector = Selector::parse("p").unwrap();
+            let marker = doc.select(&selector).find(|p| p.text().join("").trim() == config.marker);
+            if let Some(marker) = marker {
+                let html = marker.prev_siblings()
+                    .filter_map(ElementRef::wrap)
+                    .map(|elt| elt.html())
+                    .collect::<Vec<_>>();
+                fm.summary = Some(html.into_iter().rev().join(""));
+                let id = marker.id();
+                doc.tree.get_mut(id).unwrap().detach();
+            }
+        },
+        SummaryStrategy::Words => {
+            let text = display_text(doc.root_element());
+            let mut words = text.split_whitespace();
+            let mut summary = words.by_ref().take(config.words).join(" ");
+            if words.next().is_some() {
+                summary.push('…');
+            }
+            fm.summary = Some(summary).filter(|s| !s.is_empty());
+        },
+    }
+
+    let text = fm.summary.as_deref().map(|html| display_text(scraper::Html::parse_fragment(html).root_element()));
+    if let Some(text) = text {
+        fm.description = Some(description(&text, config.description_length));
+    }
+}
+
+/// Text of an element as it's displayed: text nodes are concatenated, and block elements are
+/// separated by a space so that words in different paragraphs, list items, etc. don't stick.
+fn display_text(elt: ElementRef) -> String {
+    let mut text = String::new();
+    push_display_text(elt, &mut text);
+    text
+}
+
+fn push_display_text(elt: ElementRef, text: &mut String) {
+    const BLOCKS: &[&str] = &[
+        "address", "article", "aside", "blockquote", "br", "dd", "div", "dl", "dt", "figcaption",
+        "figure", "footer", "h1", "h2", "h3", "h4", "h5", "h6", "header", "hr", "li", "main", "nav",
+        "ol", "p", "pre", "section", "table", "td", "th", "tr", "ul",
+    ];
+
+    let is_block = BLOCKS.contains(&elt.value().name());
+    if is_block {
+        text.push(' ');
+    }
+    for child in elt.children() {
+        if let Some(t) = child.value().as_text() {
+            text.push_str(t);
+        } else if let Some(child) = ElementRef::wrap(child) {
+            push_display_text(child, text);
+        }
+    }
+    if is_block {
+        text.push(' ');
+    }
+}
+
+/// Normalize whitespace and truncate to `max_len` characters, at a word boundary if possible.
+fn description(text: &str, max_len: usize) -> String {
+    let text = text.split_whitespace().join(" ");
+    if text.chars().count() <= max_len {
+        return text;
+    }
+
+    let truncated = text.chars().take(max_len.saturating_sub(1)).collect::<String>();
+    let truncated = match truncated.rfind(' ') {
+        Some(pos) if pos > 0 => &truncated[..pos],
+        _ => &truncated,
+    };
+    format!("{}…", truncated.trim_end_matches(|c: char| c.is_ascii_punctuation()))
+}
+
 
 /// `<img>` - import pictures
 /// The `resolver` takes an image reference (id & src) and returns the new value for the `src` attribute.
@@ -126,7 +287,11 @@ pub fn import_img_elts(doc: &mut scraper::Html, resolver: impl Fn(&ImageReferenc
                 // Do not download and resize
                 None
             } else {
-                let img_id = elt.value().attrs.get(&qname!("id")).unwrap().to_string();
+                // Images of HTML exports have no id
+                let img_id = match elt.value().attrs.get(&qname!("id")) {
+                    Some(id) => id.to_string(),
+                    None => format!("{:X}", stable_hash(&src)),
+                };
                 Some((elt.id(), img_id, src))
             }
         })
@@ -159,6 +324,45 @@ pub fn import_img_elts(doc: &mut scraper::Html, resolver: impl Fn(&ImageReferenc
     Ok(())
 }
 
+/// `<a>` - import linked Drive files (Sheets, Slides, PDFs, etc.)
+/// The `resolver` takes a Drive file id and returns the new value for the `href` attribute if the
+/// file was imported, or `None` to keep the link unchanged.
+///
+pub fn import_drive_links(doc: &mut scraper::Html, resolver: impl Fn(&str) -> Result<Option<String>>) -> Result<()> {
+    let selector = Selector::parse("a").unwrap();
+
+    let ids_and_file_ids = doc
+        .select(&selector)
+        .filter_map(|elt| {
+            let href = elt.value().attrs.get(&qname!("href"))?;
+            let file_id = gdocs_site::get_drive_file_id(href)?;
+            Some((elt.id(), file_id.to_string()))
+        })
+        .collect::<Vec<_>>();
+
+    // A file can be linked several times in a page
+    let mut new_hrefs = HashMap::<String, Option<String>>::new();
+
+    for (id, file_id) in ids_and_file_ids {
+        let new_href = match new_hrefs.entry(file_id) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let new_href = resolver(entry.key())?;
+                entry.insert(new_href)
+            }
+        };
+
+        if let Some(new_href) = new_href {
+            let mut node = doc.tree.get_mut(id).unwrap();
+            if let scraper::Node::Element(elt) = node.value() {
+                elt.attrs.insert(qname!("href"), new_href.as_str().into());
+            }
+        }
+    }
+
+    Ok(())
+}
+
 pub fn move_bootstrap_btn_classes(doc: &mut scraper::Html) -> Result<()> {
 
     // FIXME: scraper's DOM (ego-tree) is a major PITA to mutate several node values
@@ -236,3 +440,96 @@ pub fn move_bootstrap_btn_classes(doc: &mut scraper::Html) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{SummaryConfig, SummaryStrategy};
+    use crate::hugo_site::FrontMatter;
+
+    fn summarize(html: &str, config: &SummaryConfig) -> FrontMatter {
+        let mut dom = scraper::Html::parse_document(html);
+        let mut fm = FrontMatter::default();
+        super::extract_title_and_summary(&mut dom, &mut fm, config).unwrap();
+        fm
+    }
+
+    #[test]
+    fn test_summary_strategies() {
+        let html = "<p>An  <b>intro</b>\n text.</p><h1>Title</h1><p>First part.</p><p>&lt;!--more--&gt;</p><p>Rest of the page.</p>";
+
+        let fm = summarize(html, &SummaryConfig::default());
+        assert_eq!("Title", fm.title);
+        assert_eq!(Some("An   intro \n text. "), fm.summary.as_deref());
+
+        let config = SummaryConfig { strategy: SummaryStrategy::BeforeH1Html, description_length: 10, ..SummaryConfig::default() };
+        let fm = summarize(html, &config);
+        assert_eq!(Some("<p>An  <b>intro</b>\n text.</p>"), fm.summary.as_deref());
+        assert_eq!(Some("An intro…"), fm.description.as_deref());
+
+        let config = SummaryConfig { strategy: SummaryStrategy::Marker, ..SummaryConfig::default() };
+        let fm = summarize(html, &config);
+        assert_eq!(Some("<p>First part.</p>"), fm.summary.as_deref());
+        assert_eq!(Some("First part."), fm.description.as_deref());
+
+        let config = SummaryConfig { strategy: SummaryStrategy::Words, words: 3, ..SummaryConfig::default() };
+        let fm = summarize("<h1>Title</h1><p>Some <i>short</i></p><p>text.</p>", &config);
+        assert_eq!(Some("Some short text."), fm.summary.as_deref());
+        let fm = summarize("<h1>Title</h1><p>Some longer text.</p>", &SummaryConfig { words: 2, ..config });
+        assert_eq!(Some("Some longer…"), fm.summary.as_deref());
+
+        // Inline formatting doesn't add spaces
+        let fm = summarize("<h1>Title</h1><p><b>Intro</b>, then hel<b>lo</b>!</p><ul><li>One</li><li>two</li></ul>", &SummaryConfig { strategy: SummaryStrategy::Words, words: 10, ..SummaryConfig::default() });
+        assert_eq!(Some("Intro, then hello! One two"), fm.summary.as_deref());
+
+        let config = SummaryConfig { strategy: SummaryStrategy::BeforeH1Html, ..SummaryConfig::default() };
+        let fm = summarize("<p><i>Tea</i>: a <a href='/drinks'>drink</a>.</p><p>Hot.</p><h1>Title</h1>", &config);
+        assert_eq!(Some("Tea: a drink. Hot."), fm.description.as_deref());
+    }
+
+    #[test]
+    fn test_domain_matches() {
+        assert!(super::domain_matches("example.com", "EXAMPLE.com"));
+        assert!(!super::domain_matches("example.com", "www.example.com"));
+
+        assert!(super::domain_matches("*.example.com", "example.com"));
+        assert!(super::domain_matches("*.example.com", "www.example.com"));
+        assert!(super::domain_matches("*.example.com", "a.b.Example.com"));
+        assert!(!super::domain_matches("*.example.com", "evilexample.com"));
+        assert!(!super::domain_matches("*.example.com", "example.com.evil.org"));
+    }
+
+    #[test]
+    fn test_external_links() {
+        let links: crate::config::LinksConfig = serde_yaml::from_str(indoc::indoc! {"
+            internal_domains: ['*.mysite.org']
+            rules:
+              - domains: ['shop.example.com']
+                rel: sponsored
+              - domains: ['*.example.com']
+                target: per_domain
+                class: external
+            default:
+              target: _blank
+        "}).unwrap();
+
+        let rel = |domain| super::external_link_rule(&links, domain).and_then(|rule| rule.rel.as_deref());
+        assert!(super::external_link_rule(&links, "www.mysite.org").is_none());
+        // First matching rule
+        assert_eq!(Some("sponsored"), rel("shop.example.com"));
+        assert_eq!(None, rel("www.example.com"));
+        assert_eq!(Some("_blank"), super::external_link_rule(&links, "other.org").and_then(|rule| rule.target.as_deref()));
+
+        let mut dom = scraper::Html::parse_fragment(
+            "<a href='https://www.example.com/a'>a</a><a href='https://mysite.org/b'>b</a>"
+        );
+        super::rewrite_links(&mut dom, &crate::SiteData::default(), &links, "/page", None).unwrap();
+        // Attribute order isn't stable in serialized html
+        let selector = scraper::Selector::parse("a").unwrap();
+        let links = dom.select(&selector).map(|a| a.value()).collect::<Vec<_>>();
+        // The per-domain target must not change between runs or versions
+        assert_eq!(Some("ACC7E7B8B7A0236B"), links[0].attr("target"));
+        assert_eq!(Some("external"), links[0].attr("class"));
+        assert_eq!(Some("https://www.example.com/a"), links[0].attr("href"));
+        assert_eq!(1, links[1].attrs().count());
+    }
+}
@@ -12,3 +12,23 @@ fn test_render_index_page() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_render_revision_export() -> anyhow::Result<()> {
+
+    let html = std::fs::read_to_string("tests/data/revision/export.html")?;
+    let sanitize = gdocs2hugo::config::SanitizeConfig::default();
+    let options = gdocs2hugo::gdoc_to_html::RenderOptions { styling: None, sanitize: Some(&sanitize) };
+    let rendered = gdocs2hugo::gdoc_to_html::render_export(&html, &options)?;
+
+    insta::assert_snapshot!("revision_export", rendered.html);
+    assert!(rendered.warnings.is_empty(), "{:?}", rendered.warnings);
+
+    // Styles become classes of the site stylesheet
+    let styling = gdocs2hugo::config::StylingConfig { mode: gdocs2hugo::config::StylingMode::Classes, ..Default::default() };
+    let options = gdocs2hugo::gdoc_to_html::RenderOptions { styling: Some(&styling), sanitize: Some(&sanitize) };
+    let rendered = gdocs2hugo::gdoc_to_html::render_export(&html, &options)?;
+    assert!(!rendered.html.contains("style="));
+    assert!(rendered.styles.to_css().contains("font-weight:700;"));
+
+    Ok(())
+}